@@ -0,0 +1,157 @@
+use std::time::{Duration, Instant};
+
+use ev3dev_lang_rust::{Ev3Result, PowerSupply};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Context, IcarusResult},
+    indicator::{self, Cue},
+    line_follow::LineFollowParameters,
+    Icarus, LineFollowRobot,
+};
+
+// How often the voltage is looked at while running
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// Compensation never scales by more than this either way
+const MAX_SCALE: f32 = 1.25;
+
+/// What to scale against the battery voltage.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compensation {
+    Off,
+    Speed,
+    Gain,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatteryConfig {
+    /// Warn (and show it on the LEDs) below this
+    pub warn_volts: f32,
+    /// Refuse to calibrate below this
+    pub min_volts: f32,
+    /// The voltage the parameters were tuned at
+    pub nominal_volts: f32,
+    pub compensation: Compensation,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        return Self {
+            warn_volts: 7.2,
+            min_volts: 6.8,
+            nominal_volts: 7.8,
+            compensation: Compensation::Off,
+        };
+    }
+}
+
+pub struct Battery {
+    supply: PowerSupply,
+    config: BatteryConfig,
+    last_check: Instant,
+    warned: bool,
+    // Measured when the current run started, fixed so a trace matches its header
+    run_volts: Option<f32>,
+}
+
+impl Battery {
+    pub fn new(config: BatteryConfig) -> Ev3Result<Self> {
+        return Ok(Self {
+            supply: PowerSupply::new()?,
+            config,
+            last_check: Instant::now(),
+            warned: false,
+            run_volts: None,
+        });
+    }
+
+    pub fn volts(&self) -> Ev3Result<f32> {
+        return Ok(self.supply.get_voltage_now()? as f32 / 1_000_000.);
+    }
+
+    /// Measures the voltage a run starts at, which sets the compensation until the next
+    /// run. `Err` with the voltage when it is too low to start at all.
+    pub fn start_run(&mut self) -> IcarusResult<Result<f32, f32>> {
+        let volts = self.volts().context(&self.supply, "battery", "read voltage")?;
+        self.last_check = Instant::now();
+        self.warn_if_low(volts);
+        if volts < self.config.min_volts {
+            return Ok(Err(volts));
+        }
+        self.run_volts = Some(volts);
+        match self.config.compensation {
+            Compensation::Off => Icarus::info(format!("Battery at {:.2} V", volts)),
+            compensation => Icarus::info(format!(
+                "Battery at {:.2} V, scaling {:?} by {:.3}",
+                volts,
+                compensation,
+                self.scale()
+            )),
+        }
+        return Ok(Ok(volts));
+    }
+
+    /// Looks at the voltage now and then, warning once if it has dropped too far.
+    pub fn check(&mut self) {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return;
+        }
+        self.last_check = Instant::now();
+        match self.volts() {
+            Ok(volts) => {
+                Icarus::debug(format!("Battery at {:.2} V", volts));
+                self.warn_if_low(volts);
+            }
+            Err(e) => Icarus::debug(format!("Battery read failed: {:?}", e)),
+        }
+    }
+
+    fn warn_if_low(&mut self, volts: f32) {
+        if volts < self.config.warn_volts && !self.warned {
+            self.warned = true;
+            Icarus::warn(format!("Battery low: {:.2} V", volts));
+            indicator::cue(Cue::LowBattery);
+        }
+    }
+
+    pub fn run_volts(&self) -> Option<f32> {
+        return self.run_volts;
+    }
+
+    /// How much to scale by to behave as at the nominal voltage; 1 with compensation off.
+    pub fn scale(&self) -> f32 {
+        return match (self.config.compensation, self.run_volts) {
+            (Compensation::Off, _) | (_, None) => 1.,
+            (_, Some(volts)) => (self.config.nominal_volts / volts).clamp(1. / MAX_SCALE, MAX_SCALE),
+        };
+    }
+
+    /// `params` as they should be run at the current battery voltage.
+    pub fn compensate(&self, params: &LineFollowParameters) -> LineFollowParameters {
+        let mut params = params.clone();
+        match self.config.compensation {
+            Compensation::Off => {}
+            Compensation::Speed => {
+                params.targeted_speed = (params.targeted_speed as f32 * self.scale()).round() as i32;
+                params.edge.targeted_speed = (params.edge.targeted_speed as f32 * self.scale()).round() as i32;
+            }
+            Compensation::Gain => {
+                params.kp *= self.scale();
+                params.edge.kp *= self.scale();
+            }
+        }
+        return params;
+    }
+}
+
+impl LineFollowRobot {
+    /// The line follow parameters with battery compensation applied.
+    pub fn effective_parameters(&self) -> LineFollowParameters {
+        return match &self.battery {
+            Some(battery) => battery.compensate(&self.parameters),
+            None => self.parameters.clone(),
+        };
+    }
+}
@@ -0,0 +1,59 @@
+//! Receives the robot's telemetry stream and prints it, optionally saving it to a file.
+//!
+//! Usage: telemetry [bind address, default 0.0.0.0:5005] [output file]
+
+use std::{
+    env,
+    fs::File,
+    io::{BufWriter, Write},
+    net::UdpSocket,
+    process,
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let bind = args.get(1).map(String::as_str).unwrap_or("0.0.0.0:5005");
+
+    let socket = UdpSocket::bind(bind).unwrap_or_else(|e| {
+        eprintln!("Could not listen on {}: {}", bind, e);
+        process::exit(1);
+    });
+    let mut output = args.get(2).map(|path| {
+        BufWriter::new(File::create(path).unwrap_or_else(|e| {
+            eprintln!("Could not create {}: {}", path, e);
+            process::exit(1);
+        }))
+    });
+    let mut wrote_header = false;
+
+    println!("Listening for telemetry on {}", bind);
+    let mut buf = [0u8; 2048];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Receive failed: {}", e);
+                continue;
+            }
+        };
+        let line = String::from_utf8_lossy(&buf[..len]);
+        println!("{} {}", from.ip(), line);
+
+        if let Some(out) = &mut output {
+            // The header repeats in the stream, the file only needs it once
+            let header = line.strip_prefix("# ");
+            let result = match header {
+                Some(columns) if !wrote_header => {
+                    wrote_header = true;
+                    writeln!(out, "{}", columns)
+                }
+                Some(_) => Ok(()),
+                None => writeln!(out, "{}", line).and_then(|_| out.flush()),
+            };
+            if let Err(e) = result {
+                eprintln!("Write failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    mem,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::Path,
+};
+
+// From linux/input-event-codes.h
+const EV_KEY: u16 = 1;
+const KEY_BACKSPACE: u16 = 14;
+const KEY_ENTER: u16 = 28;
+const KEY_UP: u16 = 103;
+const KEY_LEFT: u16 = 105;
+const KEY_RIGHT: u16 = 106;
+const KEY_DOWN: u16 = 108;
+
+pub const DEFAULT_INPUT_DEVICE: &str = "/dev/input/by-path/platform-gpio_keys-event";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Back,
+}
+
+enum Event {
+    Press(Button),
+    Other,
+    // Nothing to read right now
+    Pending,
+    // Nothing to read ever again
+    End,
+}
+
+/// Reads EV3 brick button presses from a Linux input event device. Anything producing
+/// `struct input_event` records works, including a plain file of recorded events, which
+/// reads as "no more presses" once exhausted.
+pub struct Buttons {
+    device: File,
+}
+
+impl Buttons {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let device = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(path)?;
+        return Ok(Self { device });
+    }
+
+    /// Blocks until a button is pressed. `None` once the device has nothing more to give.
+    pub fn next_press(&mut self) -> io::Result<Option<Button>> {
+        loop {
+            self.wait_readable()?;
+            match self.read_event()? {
+                Event::Press(button) => return Ok(Some(button)),
+                Event::End => return Ok(None),
+                Event::Other | Event::Pending => {}
+            }
+        }
+    }
+
+    /// The next press already waiting, if any, without blocking.
+    pub fn poll_press(&mut self) -> io::Result<Option<Button>> {
+        loop {
+            match self.read_event()? {
+                Event::Press(button) => return Ok(Some(button)),
+                Event::Other => {}
+                Event::Pending | Event::End => return Ok(None),
+            }
+        }
+    }
+
+    fn wait_readable(&self) -> io::Result<()> {
+        let mut fd = libc::pollfd {
+            fd: self.device.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            if unsafe { libc::poll(&mut fd, 1, -1) } >= 0 {
+                return Ok(());
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+
+    fn read_event(&mut self) -> io::Result<Event> {
+        // struct input_event is a timeval followed by type, code and value, so its size depends on the platform
+        let time_size = mem::size_of::<libc::timeval>();
+        let mut event = vec![0u8; time_size + 8];
+        match self.device.read_exact(&mut event) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Event::End),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Event::Pending),
+            Err(e) => return Err(e),
+        }
+        let field = |offset: usize| u16::from_ne_bytes([event[time_size + offset], event[time_size + offset + 1]]);
+        let value = i32::from_ne_bytes([
+            event[time_size + 4],
+            event[time_size + 5],
+            event[time_size + 6],
+            event[time_size + 7],
+        ]);
+
+        // Only presses, not releases or autorepeat
+        if field(0) != EV_KEY || value != 1 {
+            return Ok(Event::Other);
+        }
+        let button = match field(2) {
+            KEY_UP => Button::Up,
+            KEY_DOWN => Button::Down,
+            KEY_LEFT => Button::Left,
+            KEY_RIGHT => Button::Right,
+            KEY_ENTER => Button::Enter,
+            KEY_BACKSPACE => Button::Back,
+            _ => return Ok(Event::Other),
+        };
+        return Ok(Event::Press(button));
+    }
+}
@@ -1,9 +1,73 @@
-use ev3dev_lang_rust::Ev3Result;
+use std::{str::FromStr, time::Duration};
 
-use crate::{LineFollowRobot, Icarus};
+use ev3dev_lang_rust::{motors::MediumMotor, Ev3Result};
+
+use crate::{
+    error::{Context, IcarusError, IcarusResult},
+    mission::Phase,
+    motion::BlockingMotor,
+    LineFollowRobot, Icarus,
+};
+
+// Distance (cm) at which the claw can reach the can
+const GRASP_DISTANCE: f32 = 10.;
+// A claw that closes less than this many rotations has stopped on something
+const GRIP_CLOSED_TRAVEL: f32 = 0.85;
+// A claw that closes less than this never got moving, which says nothing about a can
+const GRIP_MIN_TRAVEL: f32 = 0.1;
+// Claw open/close speed (counts/s)
+const CLAW_SPEED: i32 = 400;
+// Claw raise/lower speed (counts/s)
+const CLAW_LIFT_SPEED: i32 = 200;
+// Duty cycle (%) the claw only reaches when pushing against an object
+const GRIP_DUTY_CYCLE: i32 = 60;
+const MAX_GRASP_ATTEMPTS: u32 = 3;
+// Beyond this distance (cm) the can is considered lost
+const SEARCH_DISTANCE: f32 = 30.;
+// Approach steps between arc scans
+const RESCAN_INTERVAL: u32 = 5;
+// Scan arc is SCAN_STEPS steps of SCAN_STEP_COUNTS wheel counts either side of the bearing
+const SCAN_STEPS: i32 = 3;
+const SCAN_STEP_COUNTS: i32 = 15;
+
+/// A single claw movement, for exercising the claw on its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClawMove {
+    Down,
+    Up,
+    Open,
+    Close,
+}
+
+impl FromStr for ClawMove {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "down" => Ok(ClawMove::Down),
+            "up" => Ok(ClawMove::Up),
+            "open" => Ok(ClawMove::Open),
+            "close" => Ok(ClawMove::Close),
+            _ => Err(format!("Unknown claw move {:?}, expected down, up, open or close", s)),
+        };
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Approach {
+    Reached,
+    Lost,
+}
+
+#[derive(Debug, PartialEq)]
+enum Grasp {
+    Held,
+    Missed,
+}
 
 impl LineFollowRobot {
 
+    #[allow(dead_code)]
     fn find_cans(&self) -> Ev3Result<Vec<i32>> { // Degrees from 0° at which any cans were found
 
         let mut detected_objects = Vec::<i32>::new();
@@ -26,37 +90,181 @@ impl LineFollowRobot {
         return Ok(detected_objects);
     }
 
-    fn pickup_can(&self) -> Ev3Result<()> {
-        if self.ultrasonic.get_distance_centimeters()? < 30. {
-            self.ultrasonic.set_mode_us_dist_cm()?;
-            println!("Chasing can");
-            while self.ultrasonic.get_distance_centimeters()? > 10. {
-                // Move fowards
-                self.left_motor.set_time_sp(100)?;
-                self.right_motor.set_time_sp(100)?;
-                self.left_motor.set_speed_sp(self.parameters.targeted_speed / 3)?;
-                self.right_motor.set_speed_sp(self.parameters.targeted_speed / 3)?;
-                self.left_motor.run_timed(None)?;
-                self.right_motor.run_timed(None)?;
+    /// Runs one claw move, or down, close, up, open in turn if none is given.
+    pub fn exercise_claw(&self, claw_move: Option<ClawMove>) -> IcarusResult<()> {
+        let moves = match claw_move {
+            Some(claw_move) => vec![claw_move],
+            None => vec![ClawMove::Down, ClawMove::Close, ClawMove::Up, ClawMove::Open],
+        };
+        for claw_move in moves {
+            Icarus::info(format!("Claw {:?}", claw_move));
+            match claw_move {
+                ClawMove::Down => self.claw_vert.run_rotations(0.25, CLAW_LIFT_SPEED)?,
+                ClawMove::Up => self.claw_vert.run_rotations(-0.25, CLAW_LIFT_SPEED)?,
+                ClawMove::Close => self.claw_horiz.run_rotations(1., CLAW_SPEED)?,
+                ClawMove::Open => self.claw_horiz.run_rotations(-1., CLAW_SPEED)?,
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn pickup_can(&self) -> IcarusResult<()> {
+        if self.distance()? < SEARCH_DISTANCE {
+            self.ultrasonic.set_mode_us_dist_cm().context(&self.ultrasonic, "ultrasonic", "set distance mode")?;
+            for attempt in 1..=MAX_GRASP_ATTEMPTS {
+                Icarus::info("Chasing can".to_string());
+                if self.approach_can()? == Approach::Lost {
+                    Icarus::warn("Lost sight of can during approach".to_string());
+                    return Ok(());
+                }
+
+                if self.grasp_can()? == Grasp::Held {
+                    // Reverse (example)
+                    self.left_motor.set_time_sp(100).context(&self.left_motor, "left motor", "set time")?;
+                    self.right_motor.set_time_sp(100).context(&self.right_motor, "right motor", "set time")?;
+                    self.left_motor.set_speed_sp(-self.parameters.targeted_speed).context(&self.left_motor, "left motor", "set speed")?;
+                    self.right_motor.set_speed_sp(-self.parameters.targeted_speed).context(&self.right_motor, "right motor", "set speed")?;
+                    self.left_motor.run_timed(None).context(&self.left_motor, "left motor", "run")?;
+                    self.right_motor.run_timed(None).context(&self.right_motor, "right motor", "run")?;
+                    return Ok(());
+                }
+
+                Icarus::warn(format!("Grasp attempt {} of {} failed", attempt, MAX_GRASP_ATTEMPTS));
+
+                // Back off so the next approach has room to line up
+                let speed = -self.parameters.targeted_speed / 3;
+                self.drive_timed(Duration::from_millis(400), speed, speed)?;
+            }
+            return Err(IcarusError::CanMissed { attempts: MAX_GRASP_ATTEMPTS });
+        }
+
+        return Ok(());
+    }
+
+    // Drives at the can until it is within reach, sweeping a small arc every few steps
+    // and turning to the centre of the closest return so an off axis can stays in the beam
+    fn approach_can(&self) -> IcarusResult<Approach> {
+        let mut last_dist = self.distance()?;
+        let mut steps = 0;
+        loop {
+            let dist = self.distance()?;
+            if dist <= GRASP_DISTANCE {
+                return Ok(Approach::Reached);
+            }
+
+            // Re-centre periodically, or straight away if the can seems to have slid out of the beam
+            if steps % RESCAN_INTERVAL == 0 || dist > last_dist + 5. {
+                match self.centre_on_can()? {
+                    Some(centred_dist) => last_dist = centred_dist,
+                    None => return Ok(Approach::Lost),
+                }
+            } else {
+                last_dist = dist;
+            }
+
+            // Slow down as we close in, but never so much that we stall
+            let range_fraction = ((last_dist - GRASP_DISTANCE) / (SEARCH_DISTANCE - GRASP_DISTANCE)).clamp(0., 1.);
+            let speed = ((self.parameters.targeted_speed as f32 * range_fraction) as i32)
+                .max(self.parameters.targeted_speed / 5);
+
+            // Move fowards
+            self.drive_timed(Duration::from_millis(100), speed, speed)?;
+
+            steps += 1;
+        }
+    }
+
+    // Sweeps SCAN_STEPS either side of the current bearing and turns to face the middle of the
+    // closest reading. Returns the distance there, or None if nothing is within search range.
+    fn centre_on_can(&self) -> IcarusResult<Option<f32>> {
+        self.pivot(-SCAN_STEPS * SCAN_STEP_COUNTS)?;
+        let mut readings = Vec::<f32>::new();
+        for step in 0..=(2 * SCAN_STEPS) {
+            if step > 0 {
+                self.pivot(SCAN_STEP_COUNTS)?;
             }
+            readings.push(self.distance()?);
+        }
+
+        let closest = readings.iter().cloned().fold(f32::INFINITY, f32::min);
+        if closest > SEARCH_DISTANCE {
+            self.pivot(-SCAN_STEPS * SCAN_STEP_COUNTS)?;
+            return Ok(None);
+        }
 
-            // Move claw into down position
-            self.claw_vert.run_to_rel_pos(Some((0.25 * self.claw_vert.get_count_per_rot()? as f32) as i32))?;
-            // Close
-            self.claw_horiz.run_to_rel_pos(Some((1. * self.claw_horiz.get_count_per_rot()? as f32) as i32))?;
-            // Pickup
-            self.claw_vert.run_to_rel_pos(Some((-0.25 * self.claw_vert.get_count_per_rot()? as f32) as i32))?;
-            // Reverse (example)
-            self.left_motor.set_time_sp(100)?;
-            self.right_motor.set_time_sp(100)?;
-            self.left_motor.set_speed_sp(-self.parameters.targeted_speed)?;
-            self.right_motor.set_speed_sp(-self.parameters.targeted_speed)?;
-            self.left_motor.run_timed(None)?;
-            self.right_motor.run_timed(None)?;
+        // The beam is wider than the can, so take the middle of the run of near-closest readings
+        let peak: Vec<i32> = (0..readings.len() as i32)
+            .filter(|i| readings[*i as usize] <= closest + 1.)
+            .collect();
+        let centre = (peak[0] + peak[peak.len() - 1]) / 2;
+        self.pivot((centre - 2 * SCAN_STEPS) * SCAN_STEP_COUNTS)?;
 
+        return Ok(Some(closest));
+    }
+
+    // Spins on the spot by the given number of encoder counts (positive is clockwise)
+    fn pivot(&self, counts: i32) -> IcarusResult<()> {
+        if counts == 0 {
+            return Ok(());
         }
+        let speed = self.parameters.targeted_speed / 3;
+        let rotations = counts as f32 / self.left_motor.get_count_per_rot().context(&self.left_motor, "left motor", "read counts per rotation")? as f32;
+        return self.drive_rotations(rotations, -rotations, speed, -speed);
+    }
 
-        Ok(())
+    // Lowers, closes and lifts the claw, then checks that a can actually came with it.
+    // On a miss the claw is left open and raised, ready for another approach.
+    fn grasp_can(&self) -> IcarusResult<Grasp> {
+        let horiz_rot = self.claw_horiz.get_count_per_rot().context(&self.claw_horiz, "claw", "read counts per rotation")? as f32;
+
+        // Move claw into down position
+        self.claw_vert.run_rotations(0.25, CLAW_LIFT_SPEED)?;
+
+        // Close
+        // On an empty claw this runs the full rotation; on a can it stalls partway and keeps pushing
+        let closed_from = self.claw_horiz.get_position().context(&self.claw_horiz, "claw", "read position")?;
+        self.claw_horiz.set_speed_sp(CLAW_SPEED).context(&self.claw_horiz, "claw", "set speed")?;
+        self.claw_horiz.run_to_rel_pos(Some(horiz_rot as i32)).context(&self.claw_horiz, "claw", "close")?;
+        #[cfg(target_os = "linux")]
+        self.claw_horiz.wait(
+            || {
+                self.claw_horiz
+                    .get_state()
+                    .map(|state| {
+                        state.iter().any(|s| s == MediumMotor::STATE_STALLED)
+                            || state.iter().all(|s| s != MediumMotor::STATE_RUNNING)
+                    })
+                    .unwrap_or(true)
+            },
+            Some(Duration::from_secs(2)),
+        );
+        let travel = (self.claw_horiz.get_position().context(&self.claw_horiz, "claw", "read position")? - closed_from) as f32 / horiz_rot;
+        let duty = self.claw_horiz.get_duty_cycle().context(&self.claw_horiz, "claw", "read duty cycle")?;
+        let gripping = travel > GRIP_MIN_TRAVEL && (travel < GRIP_CLOSED_TRAVEL || duty.abs() > GRIP_DUTY_CYCLE);
+        Icarus::debug(format!("Claw travel: {:.2} rot, duty: {}%", travel, duty));
+
+        // Pickup
+        self.claw_vert.run_rotations(-0.25, CLAW_LIFT_SPEED)?;
+
+        // A lifted can clears the beam, a missed one is still standing in front of us
+        let lifted = self.distance()? > GRASP_DISTANCE;
+
+        if gripping && lifted {
+            return Ok(Grasp::Held);
+        }
+
+        Icarus::debug(format!("Grasp missed (gripping: {}, lifted: {})", gripping, lifted));
+
+        // Open back up to where we started
+        self.claw_horiz.set_speed_sp(CLAW_SPEED).context(&self.claw_horiz, "claw", "set speed")?;
+        self.claw_horiz.run_to_abs_pos(Some(closed_from)).context(&self.claw_horiz, "claw", "open")?;
+        self.claw_horiz.wait_or_timeout(Duration::from_secs(2))?;
+
+        return Ok(Grasp::Missed);
+    }
+
+    fn distance(&self) -> IcarusResult<f32> {
+        return self.ultrasonic.get_distance_centimeters().context(&self.ultrasonic, "ultrasonic", "read distance");
     }
 
     pub fn roh_tah_tey(&self) {
@@ -65,41 +273,43 @@ impl LineFollowRobot {
         }
     }
 
-    pub fn chemical_spill(&self) -> Ev3Result<()> {
+    pub fn chemical_spill(&self) -> IcarusResult<()> {
 
+        Phase::ChemicalSpill.enter();
         Icarus::info("Entering chemical spill".to_string());
 
         // Put your hands in the air like you just don't care
-        self.claw_vert.set_speed_sp(200)?;
-        self.claw_vert.set_position_sp((0.25 * self.claw_vert.get_count_per_rot()? as f32) as i32)?;
-        self.claw_vert.run_to_rel_pos(None)?;
+        let lift = self.claw_vert.get_count_per_rot().context(&self.claw_vert, "claw lift", "read counts per rotation")?;
+        self.claw_vert.set_speed_sp(CLAW_LIFT_SPEED).context(&self.claw_vert, "claw lift", "set speed")?;
+        self.claw_vert.set_position_sp((0.25 * lift as f32) as i32).context(&self.claw_vert, "claw lift", "set position")?;
+        self.claw_vert.run_to_rel_pos(None).context(&self.claw_vert, "claw lift", "raise")?;
 
 
         // Move into the center
-        self.left_motor.set_speed_sp(400)?;
-        self.right_motor.set_speed_sp(400)?;
-        self.left_motor.set_time_sp(2000)?;
-        self.right_motor.set_time_sp(2000)?;
-        self.left_motor.run_timed(None)?;
-        self.right_motor.run_timed(None)?;
+        self.left_motor.set_speed_sp(400).context(&self.left_motor, "left motor", "set speed")?;
+        self.right_motor.set_speed_sp(400).context(&self.right_motor, "right motor", "set speed")?;
+        self.left_motor.set_time_sp(2000).context(&self.left_motor, "left motor", "set time")?;
+        self.right_motor.set_time_sp(2000).context(&self.right_motor, "right motor", "set time")?;
+        self.left_motor.run_timed(None).context(&self.left_motor, "left motor", "run")?;
+        self.right_motor.run_timed(None).context(&self.right_motor, "right motor", "run")?;
 
         // Spin
-        self.ultrasonic.set_mode_us_dist_cm()?;
+        self.ultrasonic.set_mode_us_dist_cm().context(&self.ultrasonic, "ultrasonic", "set distance mode")?;
         let mut spotted_can = false;
         let mut spin_count = 0;
         while !spotted_can {
-            let dist = self.ultrasonic.get_distance_centimeters()?;
+            let dist = self.distance()?;
             if dist < 20. {
                 spotted_can = true;
             }
             
             // Pirouette slightly
-            self.left_motor.set_speed_sp(30)?;
-            self.right_motor.set_speed_sp(-30)?;
-            self.left_motor.set_time_sp(100)?;
-            self.right_motor.set_time_sp(100)?;
-            self.left_motor.run_timed(None)?;
-            self.right_motor.run_timed(None)?;
+            self.left_motor.set_speed_sp(30).context(&self.left_motor, "left motor", "set speed")?;
+            self.right_motor.set_speed_sp(-30).context(&self.right_motor, "right motor", "set speed")?;
+            self.left_motor.set_time_sp(100).context(&self.left_motor, "left motor", "set time")?;
+            self.right_motor.set_time_sp(100).context(&self.right_motor, "right motor", "set time")?;
+            self.left_motor.run_timed(None).context(&self.left_motor, "left motor", "run")?;
+            self.right_motor.run_timed(None).context(&self.right_motor, "right motor", "run")?;
 
             Icarus::debug(format!("DIST: {:?}, sc: {:?}", dist, spin_count));
             spin_count += 1;
@@ -0,0 +1,133 @@
+use std::path::PathBuf;
+
+use crate::{chemical_spill::ClawMove, logging::Level};
+
+pub const USAGE: &str = "\
+Usage: icarus [options] [command]
+
+Commands:
+  run                        Calibrate and run the course (default)
+  calibrate                  Calibrate the colour sensors and print the result
+  follow                     Calibrate and line follow only
+  spill                      Run the chemical spill on its own
+  water-tower                Drive one water tower detour
+  claw [down|up|open|close]  Move the claw, or cycle through all moves
+  menu                       Choose what to run with the brick buttons and screen
+  selftest                   Check every sensor and motor
+  discover                   Find attached devices, identify the left sensor, save to config
+  replay <trace.csv>         Check a recorded trace against the controller
+  report <trace.csv> [log]   Summarise a run
+  sim [seconds] [radius]     Line follow a simulated circle of the given radius (cm)
+  collect <samples.csv>      Calibrate, then record colour samples labelled with the brick buttons
+  train <samples.csv> <out.json>
+                             Train a colour classifier on samples and show how well it separates them
+
+Options:
+  --config <path>            Config file (default /home/robot/icarus.json)
+  --log-level <level>        error, warn, info or debug
+  --set <key>=<value>        Override a config value, e.g. --set parameters.kp=3.5
+  -h, --help                 Show this message";
+
+pub enum Command {
+    Run,
+    Calibrate,
+    Follow,
+    Spill,
+    WaterTower,
+    Claw(Option<ClawMove>),
+    Menu,
+    Selftest,
+    Discover,
+    Replay(PathBuf),
+    Report(PathBuf, Option<PathBuf>),
+    Sim { seconds: f32, radius: f32 },
+    Collect(PathBuf),
+    Train(PathBuf, PathBuf),
+    Help,
+}
+
+impl Command {
+    /// Whether the command needs the robot's hardware.
+    pub fn on_robot(&self) -> bool {
+        return !matches!(
+            self,
+            Command::Replay(_) | Command::Report(..) | Command::Sim { .. } | Command::Train(..) | Command::Help
+        );
+    }
+}
+
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub log_level: Option<Level>,
+    pub overrides: Vec<(String, String)>,
+    pub command: Command,
+}
+
+impl Cli {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut config = None;
+        let mut log_level = None;
+        let mut overrides = Vec::new();
+        let mut positional = Vec::<String>::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or(format!("{} needs a value", flag));
+            match arg.as_str() {
+                "--config" => config = Some(PathBuf::from(value("--config")?)),
+                "--log-level" => log_level = Some(value("--log-level")?.parse()?),
+                "--set" => {
+                    let pair = value("--set")?;
+                    let (key, val) = pair
+                        .split_once('=')
+                        .ok_or(format!("--set expects key=value, got {:?}", pair))?;
+                    overrides.push((key.to_string(), val.to_string()));
+                }
+                "-h" | "--help" => positional = vec!["help".to_string()],
+                flag if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let number = |i: usize, default: f32| -> Result<f32, String> {
+            return match positional.get(i) {
+                Some(text) => text.parse().map_err(|_| format!("Expected a number, got {:?}", text)),
+                None => Ok(default),
+            };
+        };
+        let (name, rest) = match positional.split_first() {
+            Some((name, rest)) => (name.as_str(), rest),
+            None => ("run", &[][..]),
+        };
+        let command = match (name, rest.len()) {
+            ("run", 0) => Command::Run,
+            ("calibrate", 0) => Command::Calibrate,
+            ("follow", 0) => Command::Follow,
+            ("spill", 0) => Command::Spill,
+            ("water-tower", 0) => Command::WaterTower,
+            ("claw", 0) => Command::Claw(None),
+            ("claw", 1) => Command::Claw(Some(rest[0].parse()?)),
+            ("menu", 0) => Command::Menu,
+            ("selftest", 0) => Command::Selftest,
+            ("discover", 0) => Command::Discover,
+            ("replay", 1) => Command::Replay(rest[0].clone().into()),
+            ("report", 1) => Command::Report(rest[0].clone().into(), None),
+            ("report", 2) => Command::Report(rest[0].clone().into(), Some(rest[1].clone().into())),
+            ("sim", 0..=2) => Command::Sim {
+                seconds: number(1, 30.)?,
+                radius: number(2, 60.)?,
+            },
+            ("collect", 1) => Command::Collect(rest[0].clone().into()),
+            ("train", 2) => Command::Train(rest[0].clone().into(), rest[1].clone().into()),
+            ("help", _) => Command::Help,
+            _ => return Err(format!("Unexpected arguments: {}", positional.join(" "))),
+        };
+
+        return Ok(Self {
+            config,
+            log_level,
+            overrides,
+            command,
+        });
+    }
+}
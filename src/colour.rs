@@ -0,0 +1,298 @@
+use std::{fmt::Display, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::line_follow::{CalibrationProfile, RGB};
+
+// Full scale of a raw EV3 colour sensor channel
+const RAW_MAX: f32 = 1020.;
+// The linear sRGB to XYZ matrix; the sensor's channels are treated as sRGB primaries
+const XYZ: [[f32; 3]; 3] = [
+    [0.4124, 0.3576, 0.1805],
+    [0.2126, 0.7152, 0.0722],
+    [0.0193, 0.1192, 0.9505],
+];
+// How far the surface and the line's black may wander in Lab before they stop counting
+const WHITE_SPREAD: [f32; 3] = [6., 6., 6.];
+const BLACK_SPREAD: [f32; 3] = [10., 6., 6.];
+
+/// Hue in degrees, saturation and value from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+/// CIELab, relative to whatever was used as white.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl RGB {
+    /// Value is the brightest channel over a raw channel's full scale.
+    pub fn hsv(&self) -> Hsv {
+        let (r, g, b) = (self.r as f32, self.g as f32, self.b as f32);
+        let max = r.max(g).max(b);
+        let range = max - r.min(g).min(b);
+        let hue = if range <= 0. {
+            0.
+        } else if max == r {
+            60. * ((g - b) / range).rem_euclid(6.)
+        } else if max == g {
+            60. * ((b - r) / range + 2.)
+        } else {
+            60. * ((r - g) / range + 4.)
+        };
+        return Hsv {
+            h: hue,
+            s: if max > 0. { range / max } else { 0. },
+            v: (max / RAW_MAX).clamp(0., 1.),
+        };
+    }
+
+    /// Each channel's share of the total, which leaves out how bright the reading is.
+    pub fn chromaticity(&self) -> (f32, f32, f32) {
+        let total = (self.r + self.g + self.b) as f32;
+        if total <= 0. {
+            return (0., 0., 0.);
+        }
+        return (self.r as f32 / total, self.g as f32 / total, self.b as f32 / total);
+    }
+
+    /// CIELab taking `white` (normally the calibration surface) as the white point, so the
+    /// surface is L 100 with no colour whatever the lighting.
+    pub fn lab(&self, white: &RGB) -> Lab {
+        let scaled = [
+            self.r as f32 / white.r.max(1) as f32,
+            self.g as f32 / white.g.max(1) as f32,
+            self.b as f32 / white.b.max(1) as f32,
+        ];
+        let xyz = |row: &[f32; 3], rgb: [f32; 3]| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+        let f = |t: f32| if t > 0.008856 { t.max(0.).cbrt() } else { 7.787 * t + 16. / 116. };
+        let [fx, fy, fz] = [0, 1, 2].map(|i| f(xyz(&XYZ[i], scaled) / xyz(&XYZ[i], [1., 1., 1.])));
+        return Lab {
+            l: 116. * fy - 16.,
+            a: 500. * (fx - fy),
+            b: 200. * (fy - fz),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColourClass {
+    White,
+    Black,
+    Green,
+    Red,
+    Silver,
+    /// Nothing close enough to call
+    Unknown,
+}
+
+impl Display for ColourClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.write_str(match self {
+            ColourClass::White => "white",
+            ColourClass::Black => "black",
+            ColourClass::Green => "green",
+            ColourClass::Red => "red",
+            ColourClass::Silver => "silver",
+            ColourClass::Unknown => "unknown",
+        });
+    }
+}
+
+/// Where a colour sits in Lab relative to the calibration surface, and how far it strays.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Prototype {
+    pub class: ColourClass,
+    pub lab: Lab,
+    /// Standard deviation along L, a and b
+    pub spread: [f32; 3],
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColourConfig {
+    /// Detect green turns with the classifier instead of `parameters.green_threshold`
+    pub classify_green: bool,
+    /// The colours besides the surface and the line, which calibration measures
+    pub prototypes: Vec<Prototype>,
+    /// Further than this many spreads from every colour is unknown
+    pub max_distance: f32,
+    /// The best colour needs at least this share of the confidence
+    pub min_confidence: f32,
+    /// Classifier saved by `icarus train`, used in place of the prototypes
+    pub trained: Option<PathBuf>,
+}
+
+impl Default for ColourConfig {
+    fn default() -> Self {
+        let prototype = |class, l, a, b, spread| Prototype {
+            class,
+            lab: Lab { l, a, b },
+            spread,
+        };
+        return Self {
+            classify_green: false,
+            prototypes: vec![
+                prototype(ColourClass::Green, 63., -27., 19., [12., 10., 10.]),
+                prototype(ColourClass::Red, 57., 37., 23., [12., 10., 10.]),
+                // Reflective tape reads brighter than the mat
+                prototype(ColourClass::Silver, 112., 0., 0., [6., 6., 6.]),
+            ],
+            max_distance: 3.,
+            min_confidence: 0.6,
+            trained: None,
+        };
+    }
+}
+
+/// What a reading looks like, with each colour's share of the confidence.
+#[derive(Debug, Clone)]
+pub struct Classification {
+    pub class: ColourClass,
+    pub confidences: Vec<(ColourClass, f32)>,
+}
+
+impl Classification {
+    pub fn confidence(&self, class: ColourClass) -> f32 {
+        return self.confidences.iter().find(|(c, _)| *c == class).map(|(_, confidence)| *confidence).unwrap_or(0.);
+    }
+}
+
+/// Names colours by their distance in Lab from each class, scaled by how far that class
+/// strays. Readings are taken relative to the surface the sensor was calibrated on.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ColourClassifier {
+    pub prototypes: Vec<Prototype>,
+    pub max_distance: f32,
+    pub min_confidence: f32,
+}
+
+impl ColourClassifier {
+    /// The configured colours plus the surface as white and the line's black as measured
+    /// (averaged over the sensors) by `profile`.
+    pub fn from_calibration(config: &ColourConfig, profile: &CalibrationProfile) -> Self {
+        let surfaces: Vec<&RGB> = [&profile.left, &profile.right].into_iter().chain(&profile.extra).collect();
+        let black_fraction = surfaces
+            .iter()
+            .enumerate()
+            .map(|(index, surface)| profile.black(index) / surface.reflectivity().max(1.))
+            .sum::<f32>()
+            / surfaces.len() as f32;
+        // A grey has no colour, and its lightness follows from Y alone
+        let black = Lab {
+            l: 116. * black_fraction.max(0.008856).cbrt() - 16.,
+            a: 0.,
+            b: 0.,
+        };
+
+        let mut prototypes = vec![
+            Prototype {
+                class: ColourClass::White,
+                lab: Lab { l: 100., a: 0., b: 0. },
+                spread: WHITE_SPREAD,
+            },
+            Prototype {
+                class: ColourClass::Black,
+                lab: black,
+                spread: BLACK_SPREAD,
+            },
+        ];
+        prototypes.extend(config.prototypes.iter().cloned());
+        return Self {
+            prototypes,
+            max_distance: config.max_distance,
+            min_confidence: config.min_confidence,
+        };
+    }
+
+    pub fn classify(&self, reading: &RGB, surface: &RGB) -> Classification {
+        let lab = reading.lab(surface);
+        let distances: Vec<(ColourClass, f32)> = self
+            .prototypes
+            .iter()
+            .map(|prototype| {
+                let axes = [lab.l - prototype.lab.l, lab.a - prototype.lab.a, lab.b - prototype.lab.b];
+                let squared: f32 = axes.iter().zip(prototype.spread).map(|(d, spread)| (d / spread.max(0.1)).powi(2)).sum();
+                (prototype.class, squared.sqrt())
+            })
+            .collect();
+
+        // Equal priors, so each class's share of the total likelihood
+        let likelihoods: Vec<f32> = distances.iter().map(|(_, distance)| (-0.5 * distance * distance).exp()).collect();
+        let total: f32 = likelihoods.iter().sum();
+        let confidences: Vec<(ColourClass, f32)> = distances
+            .iter()
+            .zip(&likelihoods)
+            .map(|((class, _), likelihood)| (*class, if total > 0. { likelihood / total } else { 0. }))
+            .collect();
+
+        let nearest = distances.iter().zip(&confidences).min_by(|a, b| a.0 .1.total_cmp(&b.0 .1));
+        let class = match nearest {
+            Some(((class, distance), (_, confidence)))
+                if *distance <= self.max_distance && *confidence >= self.min_confidence =>
+            {
+                *class
+            }
+            _ => ColourClass::Unknown,
+        };
+        return Classification { class, confidences };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SURFACE: (i32, i32, i32) = (180, 190, 170);
+
+    fn classifier() -> ColourClassifier {
+        let surface = RGB::from(SURFACE);
+        return ColourClassifier::from_calibration(&ColourConfig::default(), &CalibrationProfile::from((surface.clone(), surface)));
+    }
+
+    fn classify(reading: (i32, i32, i32)) -> ColourClass {
+        return classifier().classify(&RGB::from(reading), &RGB::from(SURFACE)).class;
+    }
+
+    #[test]
+    fn surface_is_lab_white() {
+        let surface = RGB::from(SURFACE);
+        let lab = surface.lab(&surface);
+        assert!((lab.l - 100.).abs() < 0.01 && lab.a.abs() < 0.01 && lab.b.abs() < 0.01, "{:?}", lab);
+
+        let green = RGB::from((30, 70, 30)).lab(&surface);
+        assert!(green.a < -10. && green.l < 100., "{:?}", green);
+    }
+
+    #[test]
+    fn classifies_against_the_calibration_surface() {
+        assert_eq!(classify(SURFACE), ColourClass::White);
+        assert_eq!(classify((25, 25, 25)), ColourClass::Black);
+        assert_eq!(classify((30, 70, 30)), ColourClass::Green);
+        assert_eq!(classify((110, 30, 22)), ColourClass::Red);
+    }
+
+    #[test]
+    fn in_between_is_unknown() {
+        let classification = classifier().classify(&RGB::from((100, 105, 95)), &RGB::from(SURFACE));
+        assert_eq!(classification.class, ColourClass::Unknown);
+        let total: f32 = classification.confidences.iter().map(|(_, confidence)| confidence).sum();
+        assert!((total - 1.).abs() < 0.001);
+    }
+
+    #[test]
+    fn dimmer_lighting_keeps_the_class() {
+        // The same mat and tape under half the light
+        let dim = |(r, g, b): (i32, i32, i32)| RGB::from((r / 2, g / 2, b / 2));
+        let classification = classifier().classify(&dim((30, 70, 30)), &dim(SURFACE));
+        assert_eq!(classification.class, ColourClass::Green);
+    }
+}
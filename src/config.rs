@@ -0,0 +1,236 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ev3dev_lang_rust::{motors::MotorPort, sensors::SensorPort};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    battery::BatteryConfig,
+    colour::ColourConfig,
+    buttons::DEFAULT_INPUT_DEVICE,
+    error::{IcarusError, IcarusResult},
+    line_follow::{FollowMode, LineFollowParameters},
+    line_sensing::LineSensingConfig,
+    logging::Level,
+    menu::{MenuConfig, MIN_SCREEN_HEIGHT},
+    mission::{Phase, CHECKPOINTS},
+    sensors::SensorConfig,
+};
+
+/// Read when no `--config` is given; a missing file there just means defaults.
+pub const DEFAULT_CONFIG_PATH: &str = "/home/robot/icarus.json";
+
+/// Which port each device is plugged into, as ev3dev names them (`in1`, `outA`, ...).
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortConfig {
+    pub left_light: String,
+    pub right_light: String,
+    pub ultrasonic: String,
+    pub left_motor: String,
+    pub right_motor: String,
+    pub claw_vert: String,
+    pub claw_horiz: String,
+}
+
+impl Default for PortConfig {
+    fn default() -> Self {
+        return Self {
+            left_light: "in1".to_string(),
+            right_light: "in2".to_string(),
+            ultrasonic: "in3".to_string(),
+            left_motor: "outA".to_string(),
+            right_motor: "outB".to_string(),
+            claw_vert: "outC".to_string(),
+            claw_horiz: "outD".to_string(),
+        };
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub ports: PortConfig,
+    /// Find devices by driver at startup instead of trusting `ports`
+    pub auto_discover: bool,
+    pub parameters: LineFollowParameters,
+    /// Follow with both colour sensors, or the edge of the line with just one
+    pub follow_mode: FollowMode,
+    /// Where the downward sensors sit, and any beyond the left/right pair
+    pub line_sensors: LineSensingConfig,
+    /// Naming colours, and whether green turns go by it
+    pub colour: ColourConfig,
+    pub log_level: String,
+    pub log_file: Option<PathBuf>,
+    pub trace_dir: Option<PathBuf>,
+    /// `host:port` to stream telemetry to
+    pub telemetry: Option<String>,
+    /// `address:port` to accept remote commands on
+    pub remote: Option<String>,
+    /// Input event device the brick buttons report on
+    pub buttons: PathBuf,
+    /// Screen and profiles for `icarus menu`
+    pub menu: MenuConfig,
+    /// Hold after calibrating until the enter button or a remote start
+    pub wait_for_start: bool,
+    /// Checkpoint a run starts from
+    pub start_from: Phase,
+    /// Beep on phase changes, turns and errors as well as lighting the LEDs
+    pub sound: bool,
+    pub battery: BatteryConfig,
+    /// Read retries and reconnecting lost sensors
+    pub sensors: SensorConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Self {
+            ports: PortConfig::default(),
+            auto_discover: false,
+            parameters: LineFollowParameters::default(),
+            follow_mode: FollowMode::Both,
+            line_sensors: LineSensingConfig::default(),
+            colour: ColourConfig::default(),
+            log_level: "info".to_string(),
+            log_file: Some("/home/robot/logs/icarus.log".into()),
+            trace_dir: Some("/home/robot/traces".into()),
+            telemetry: None,
+            remote: None,
+            buttons: DEFAULT_INPUT_DEVICE.into(),
+            menu: MenuConfig::default(),
+            wait_for_start: false,
+            start_from: Phase::LineFollow,
+            sound: true,
+            battery: BatteryConfig::default(),
+            sensors: SensorConfig::default(),
+        };
+    }
+}
+
+impl Config {
+    /// Loads `path` (or the default location) and applies `key=value` overrides, where
+    /// keys are dotted paths into the config such as `parameters.kp` or `ports.left_light`.
+    pub fn load(path: Option<&Path>, overrides: &[(String, String)]) -> IcarusResult<Self> {
+        return Self::merge(path, overrides).map_err(IcarusError::ConfigInvalid);
+    }
+
+    fn merge(path: Option<&Path>, overrides: &[(String, String)]) -> Result<Self, String> {
+        let mut value = match path {
+            Some(path) => Self::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::read(DEFAULT_CONFIG_PATH.as_ref())?,
+            None => serde_json::to_value(Config::default()).map_err(|e| e.to_string())?,
+        };
+
+        for (key, raw) in overrides {
+            let mut target = &mut value;
+            for part in key.split('.') {
+                target = target
+                    .as_object_mut()
+                    .and_then(|object| object.get_mut(part))
+                    .ok_or(format!("Unknown config key {}", key))?;
+            }
+            // Numbers and booleans parse as JSON, anything else is taken as a string
+            *target = serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.clone()));
+        }
+
+        let config: Config = serde_json::from_value(value).map_err(|e| format!("Invalid config: {}", e))?;
+        config.validate()?;
+        return Ok(config);
+    }
+
+    // Parsed through the defaults so a config file only needs the keys it changes
+    fn read(path: &Path) -> Result<Value, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Config = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return serde_json::to_value(config).map_err(|e| e.to_string());
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        return fs::write(path, text + "\n").map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.log_level()?;
+        let mut sensors = vec![&self.ports.left_light, &self.ports.right_light, &self.ports.ultrasonic];
+        sensors.extend(self.line_sensors.extra.iter().map(|extra| &extra.port));
+        for (i, port) in sensors.iter().enumerate() {
+            sensor_port(port)?;
+            if sensors[..i].contains(port) {
+                return Err(format!("Sensor port {} is configured twice", port));
+            }
+        }
+        if self.line_sensors.left_offset_mm >= self.line_sensors.right_offset_mm {
+            return Err("line_sensors.left_offset_mm must be left of right_offset_mm".to_string());
+        }
+        if !(0. ..1.).contains(&self.line_sensors.black_fraction) {
+            return Err("line_sensors.black_fraction must be from 0 up to 1".to_string());
+        }
+        if self.colour.max_distance <= 0. || !(0. ..=1.).contains(&self.colour.min_confidence) {
+            return Err("colour.max_distance must be positive and colour.min_confidence from 0 to 1".to_string());
+        }
+        if self.line_sensors.black_sweep_rotations < 0. {
+            return Err("line_sensors.black_sweep_rotations cannot be negative".to_string());
+        }
+        for port in [
+            &self.ports.left_motor,
+            &self.ports.right_motor,
+            &self.ports.claw_vert,
+            &self.ports.claw_horiz,
+        ] {
+            motor_port(port)?;
+        }
+        if !CHECKPOINTS.contains(&self.start_from) {
+            return Err(format!("start_from cannot be {}", self.start_from));
+        }
+        if self.battery.min_volts > self.battery.warn_volts {
+            return Err("battery.min_volts must not be above battery.warn_volts".to_string());
+        }
+        if self.battery.nominal_volts <= 0. {
+            return Err("battery.nominal_volts must be positive".to_string());
+        }
+        if self.sensors.read_attempts == 0 {
+            return Err("sensors.read_attempts must be at least 1".to_string());
+        }
+        if self.parameters.edge.edge_fraction <= 0. || self.parameters.edge.edge_fraction >= 1. {
+            return Err("parameters.edge.edge_fraction must be between 0 and 1".to_string());
+        }
+        if self.menu.screen.height < MIN_SCREEN_HEIGHT {
+            return Err(format!("menu.screen.height must be at least {}", MIN_SCREEN_HEIGHT));
+        }
+        if self.parameters.tick == 0 {
+            return Err("parameters.tick must be at least 1 ms".to_string());
+        }
+        if self.parameters.targeted_speed <= 0 || self.parameters.edge.targeted_speed <= 0 {
+            return Err("parameters.targeted_speed and parameters.edge.targeted_speed must be positive".to_string());
+        }
+        return Ok(());
+    }
+
+    pub fn log_level(&self) -> Result<Level, String> {
+        return self.log_level.parse();
+    }
+}
+
+pub fn sensor_port(name: &str) -> Result<SensorPort, String> {
+    return match name {
+        "in1" => Ok(SensorPort::In1),
+        "in2" => Ok(SensorPort::In2),
+        "in3" => Ok(SensorPort::In3),
+        "in4" => Ok(SensorPort::In4),
+        _ => Err(format!("Unknown sensor port {:?}, expected in1 to in4", name)),
+    };
+}
+
+pub fn motor_port(name: &str) -> Result<MotorPort, String> {
+    return match name {
+        "outA" => Ok(MotorPort::OutA),
+        "outB" => Ok(MotorPort::OutB),
+        "outC" => Ok(MotorPort::OutC),
+        "outD" => Ok(MotorPort::OutD),
+        _ => Err(format!("Unknown motor port {:?}, expected outA to outD", name)),
+    };
+}
@@ -0,0 +1,190 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+    thread::sleep,
+    time::Duration,
+};
+
+use ev3dev_lang_rust::{
+    motors::{LargeMotor, MediumMotor},
+    sensors::{ColorSensor, UltrasonicSensor},
+    Device, Ev3Result,
+};
+
+use crate::{
+    config::{sensor_port, Config, PortConfig},
+    error::{IcarusError, IcarusResult},
+    line_follow::RGB,
+    line_sensing::LineSensingConfig,
+    Icarus,
+};
+
+// Raw reflectivity the covered sensor must lose before we trust it was covered
+const MIN_COVER_DROP: f32 = 40.;
+// ...and how many times the other sensor's drop it must be
+const COVER_MARGIN: f32 = 2.;
+
+/// Ports of each attached device type, in ev3dev's short form (`in1`, `outA`, ...).
+pub struct AttachedDevices {
+    pub colour_sensors: Vec<String>,
+    pub ultrasonics: Vec<String>,
+    pub large_motors: Vec<String>,
+    pub medium_motors: Vec<String>,
+}
+
+// Addresses come back as e.g. `ev3-ports:in1`
+pub fn short_port<D: Device>(device: &D) -> Ev3Result<String> {
+    let address = device.get_address()?;
+    return Ok(address.rsplit(':').next().unwrap_or(&address).to_string());
+}
+
+fn ports<D: Device>(devices: Ev3Result<Vec<D>>) -> Ev3Result<Vec<String>> {
+    let mut ports = devices?.iter().map(short_port).collect::<Ev3Result<Vec<_>>>()?;
+    ports.sort();
+    return Ok(ports);
+}
+
+pub fn attached_devices() -> IcarusResult<AttachedDevices> {
+    let list = || -> Ev3Result<AttachedDevices> {
+        return Ok(AttachedDevices {
+            colour_sensors: ports(ColorSensor::list())?,
+            ultrasonics: ports(UltrasonicSensor::list())?,
+            large_motors: ports(LargeMotor::list())?,
+            medium_motors: ports(MediumMotor::list())?,
+        });
+    };
+    return list().map_err(IcarusError::listing);
+}
+
+/// Points the config at whatever is attached, without asking anything. Devices that
+/// can't be told apart by driver keep their configured ports if those are all still
+/// present. Large motors that moved are assigned in port order with a warning; colour
+/// sensors that moved could swap left and right, so they fail until `icarus discover`
+/// identifies them. The config is left alone unless every device resolves.
+pub fn apply_discovered(config: &mut Config, found: &AttachedDevices) -> Result<(), String> {
+    config.ports = discovered_ports(config, found, None)?;
+    return Ok(());
+}
+
+// The configured ports updated to what is attached. `lights` is the left/right pair once identified.
+fn discovered_ports(config: &Config, found: &AttachedDevices, lights: Option<(String, String)>) -> Result<PortConfig, String> {
+    let mut ports = config.ports.clone();
+    let single = |kind: &str, found: &[String]| -> Result<String, String> {
+        return match found {
+            [port] => Ok(port.clone()),
+            _ => Err(format!("Expected one {}, found {:?}", kind, found)),
+        };
+    };
+    ports.ultrasonic = single("ultrasonic sensor", &found.ultrasonics)?;
+    ports.claw_horiz = single("medium motor", &found.medium_motors)?;
+
+    match lights {
+        Some((left, right)) => {
+            ports.left_light = left;
+            ports.right_light = right;
+        }
+        None => {
+            let lights = [&mut ports.left_light, &mut ports.right_light];
+            assign_group("colour sensor", lights, &pair_candidates(&config.line_sensors, &found.colour_sensors), false)?;
+        }
+    }
+    let motors = [&mut ports.left_motor, &mut ports.right_motor, &mut ports.claw_vert];
+    assign_group("large motor", motors, &found.large_motors, true)?;
+    return Ok(ports);
+}
+
+// Leaves `slots` alone if they already cover `found`. Otherwise assigns in port order if
+// `in_port_order`, or fails so the operator can identify them.
+fn assign_group<const N: usize>(kind: &str, mut slots: [&mut String; N], found: &[String], in_port_order: bool) -> Result<(), String> {
+    if found.len() != N {
+        return Err(format!("Expected {} {}s, found {:?}", N, kind, found));
+    }
+    if found.iter().all(|port| slots.iter().any(|slot| *slot == port)) {
+        return Ok(());
+    }
+    if !in_port_order {
+        return Err(format!("{}s moved to {:?}; run `icarus discover` to tell which is which", kind, found));
+    }
+    Icarus::warn(format!(
+        "{}s moved to {:?}, assigning in port order; run `icarus discover` to check",
+        kind, found
+    ));
+    for (slot, port) in slots.iter_mut().zip(found) {
+        **slot = port.clone();
+    }
+    return Ok(());
+}
+
+/// Discovers attached devices, asks the operator to cover the left colour sensor so
+/// the two can be told apart, and saves the resulting mapping to `config_path`.
+pub fn discover(config: &mut Config, config_path: &Path) -> Result<(), String> {
+    let found = attached_devices().map_err(|e| e.to_string())?;
+    Icarus::info(format!(
+        "Found colour sensors {:?}, ultrasonic {:?}, large motors {:?}, medium motors {:?}",
+        found.colour_sensors, found.ultrasonics, found.large_motors, found.medium_motors
+    ));
+    let candidates = pair_candidates(&config.line_sensors, &found.colour_sensors);
+    if candidates.len() != 2 {
+        return Err(format!("Expected 2 colour sensors, found {:?}", candidates));
+    }
+    let lights = identify_left(&candidates)?;
+    config.ports = discovered_ports(config, &found, Some(lights))?;
+
+    config.save(config_path)?;
+    Icarus::info(format!(
+        "Saved to {}: left light {}, right light {}, ultrasonic {}, motors {} {} {} {}",
+        config_path.display(),
+        config.ports.left_light,
+        config.ports.right_light,
+        config.ports.ultrasonic,
+        config.ports.left_motor,
+        config.ports.right_motor,
+        config.ports.claw_vert,
+        config.ports.claw_horiz
+    ));
+    return Ok(());
+}
+
+// Extra line sensors stay on their configured ports and are not candidates for the left/right pair
+fn pair_candidates(line_sensors: &LineSensingConfig, colour_sensors: &[String]) -> Vec<String> {
+    return colour_sensors
+        .iter()
+        .filter(|port| !line_sensors.extra.iter().any(|extra| extra.port == **port))
+        .cloned()
+        .collect();
+}
+
+// Whichever sensor darkens most when covered is the left one
+fn identify_left(ports: &[String]) -> Result<(String, String), String> {
+    let sensor_ports = ports.iter().map(|port| sensor_port(port)).collect::<Result<Vec<_>, String>>()?;
+    let read = || -> Ev3Result<Vec<f32>> {
+        let sensors = sensor_ports.iter().map(|&port| ColorSensor::get(port)).collect::<Ev3Result<Vec<_>>>()?;
+        for sensor in &sensors {
+            sensor.set_mode_rgb_raw()?;
+        }
+        sleep(Duration::from_millis(200));
+        return sensors.iter().map(|s| Ok(RGB::from(s.get_rgb()?).reflectivity())).collect();
+    };
+
+    let uncovered = read().map_err(|e| format!("{:?}", e))?;
+    prompt("Cover the LEFT colour sensor with your hand and press Enter");
+    let covered = read().map_err(|e| format!("{:?}", e))?;
+
+    let drops: Vec<f32> = uncovered.iter().zip(&covered).map(|(before, after)| before - after).collect();
+    Icarus::debug(format!("Brightness drop per sensor {:?}: {:?}", ports, drops));
+    let left = if drops[0] >= drops[1] { 0 } else { 1 };
+    // Anything less could be a shadow or noise, and guessing would swap the sides for the whole run
+    if drops[left] < MIN_COVER_DROP || drops[left] < COVER_MARGIN * drops[1 - left].max(0.) {
+        return Err(format!(
+            "Could not tell which colour sensor was covered (drops {:?} on {:?}), cover one fully and try again",
+            drops, ports
+        ));
+    }
+    return Ok((ports[left].clone(), ports[1 - left].clone()));
+}
+
+fn prompt(message: &str) {
+    print!("{} ", message);
+    let _ = io::stdout().flush();
+    let _ = io::stdin().lock().read_line(&mut String::new());
+}
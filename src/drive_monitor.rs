@@ -0,0 +1,169 @@
+use std::fmt::Display;
+
+use ev3dev_lang_rust::motors::LargeMotor;
+
+use crate::{
+    error::{Context, IcarusError, IcarusResult},
+    Icarus, LineFollowRobot,
+};
+
+// Commands slower than this (deg/s) are too noisy to judge
+const MIN_JUDGED_SPEED: i32 = 60;
+// Stalled: wheel turning at less than this fraction of its command while pushing this hard (%)
+const STALL_SPEED_FRACTION: f32 = 0.2;
+const STALL_DUTY_CYCLE: i32 = 70;
+// Slipping: wheel keeping up with its command on less than this fraction of its usual load
+const SLIP_LOAD_FRACTION: f32 = 0.5;
+// Consecutive suspicious ticks before an event is raised
+const EVENT_TICKS: u32 = 5;
+// Weight of each new tick in the running load baseline
+const LOAD_SMOOTHING: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wheel {
+    Left,
+    Right,
+}
+
+/// Something the active behavior should react to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DriveEvent {
+    /// Wheel is blocked (wall, debris) and barely turning
+    Stall(Wheel),
+    /// Wheel is spinning freely without traction (e.g. on a ramp)
+    Slip(Wheel),
+}
+
+impl Display for DriveEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            DriveEvent::Stall(wheel) => write!(f, "{:?} wheel stalled", wheel),
+            DriveEvent::Slip(wheel) => write!(f, "{:?} wheel slipping", wheel),
+        };
+    }
+}
+
+#[derive(Default)]
+struct WheelTracker {
+    // Typical duty cycle per deg/s of speed while driving normally
+    load: Option<f32>,
+    stall_ticks: u32,
+    slip_ticks: u32,
+}
+
+impl WheelTracker {
+    fn update(&mut self, wheel: Wheel, commanded: i32, measured: i32, duty: i32) -> Option<DriveEvent> {
+        if commanded.abs() < MIN_JUDGED_SPEED {
+            self.stall_ticks = 0;
+            self.slip_ticks = 0;
+            return None;
+        }
+
+        let commanded = commanded.abs() as f32;
+        let measured = measured.abs() as f32;
+        let duty = duty.abs();
+
+        if measured < STALL_SPEED_FRACTION * commanded && duty > STALL_DUTY_CYCLE {
+            self.stall_ticks += 1;
+        } else {
+            self.stall_ticks = 0;
+        }
+
+        let load = duty as f32 / measured.max(1.);
+        match self.load {
+            Some(usual) if measured >= 0.8 * commanded && load < SLIP_LOAD_FRACTION * usual => {
+                self.slip_ticks += 1;
+            }
+            Some(usual) => {
+                self.slip_ticks = 0;
+                if self.stall_ticks == 0 {
+                    self.load = Some(usual + LOAD_SMOOTHING * (load - usual));
+                }
+            }
+            None => {
+                if self.stall_ticks == 0 && measured >= 0.8 * commanded {
+                    self.load = Some(load);
+                }
+            }
+        }
+
+        if self.stall_ticks >= EVENT_TICKS {
+            self.stall_ticks = 0;
+            return Some(DriveEvent::Stall(wheel));
+        }
+        if self.slip_ticks >= EVENT_TICKS {
+            self.slip_ticks = 0;
+            return Some(DriveEvent::Slip(wheel));
+        }
+        return None;
+    }
+}
+
+/// Compares commanded speed against measured speed and duty cycle for each drive motor.
+/// Feed it once per control tick; it raises a `DriveEvent` once a condition has persisted.
+#[derive(Default)]
+pub struct DriveMonitor {
+    left: WheelTracker,
+    right: WheelTracker,
+}
+
+impl DriveMonitor {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn update(&mut self, wheel: Wheel, commanded: i32, measured: i32, duty: i32) -> Option<DriveEvent> {
+        return match wheel {
+            Wheel::Left => self.left.update(wheel, commanded, measured, duty),
+            Wheel::Right => self.right.update(wheel, commanded, measured, duty),
+        };
+    }
+}
+
+impl LineFollowRobot {
+    /// Reads both drive motors and feeds them to the monitor.
+    /// A stall takes priority over a slip if both wheels report at once.
+    pub fn check_drive(&self, monitor: &mut DriveMonitor) -> IcarusResult<Option<DriveEvent>> {
+        let left = Self::sample(&self.left_motor, "left motor")?;
+        let right = Self::sample(&self.right_motor, "right motor")?;
+        let events = [
+            monitor.update(Wheel::Left, left.0, left.1, left.2),
+            monitor.update(Wheel::Right, right.0, right.1, right.2),
+        ];
+
+        let event = events
+            .iter()
+            .flatten()
+            .find(|e| matches!(e, DriveEvent::Stall(_)))
+            .or(events.iter().flatten().next())
+            .cloned();
+        if let Some(event) = event {
+            Icarus::warn(format!("{}", event));
+        }
+        return Ok(event);
+    }
+
+    fn sample(motor: &LargeMotor, name: &'static str) -> IcarusResult<(i32, i32, i32)> {
+        return Ok((
+            motor.get_speed_sp().context(motor, name, "read speed setpoint")?,
+            motor.get_speed().context(motor, name, "read speed")?,
+            motor.get_duty_cycle().context(motor, name, "read duty cycle")?,
+        ));
+    }
+
+    /// Reverses a short way so a stalled robot can have another go.
+    pub fn back_off(&self) -> IcarusResult<()> {
+        let back_off_rotations = 0.5;
+        let speed = self.parameters.targeted_speed;
+        self.stop_drive()?;
+        return self.drive_rotations(-back_off_rotations, -back_off_rotations, -speed, -speed);
+    }
+
+    /// Backs off after a stall. A wheel that can't even do that is stuck for good.
+    pub fn free_stall(&self, wheel: Wheel) -> IcarusResult<()> {
+        return self.back_off().map_err(|e| match e {
+            IcarusError::Timeout { phase, .. } => IcarusError::Stall { wheel, phase },
+            e => e,
+        });
+    }
+}
@@ -0,0 +1,109 @@
+use std::{fmt::Display, time::Duration};
+
+use ev3dev_lang_rust::{Device, Ev3Error, Ev3Result};
+
+use crate::{discovery::short_port, drive_monitor::Wheel, mission::Phase};
+
+#[derive(Debug)]
+pub enum IcarusError {
+    /// Talking to a device failed. `device` is what the robot calls it, e.g. "left colour sensor".
+    Device {
+        device: &'static str,
+        port: Option<String>,
+        operation: &'static str,
+        phase: Phase,
+        source: Ev3Error,
+    },
+    /// A motor was still running when its deadline passed. It has been stopped.
+    Timeout { motor: String, deadline: Duration, phase: Phase },
+    /// A motor was asked to move at speed 0, which it would never finish.
+    Stationary { motor: String, phase: Phase },
+    CalibrationMissing { phase: Phase },
+    ConfigInvalid(String),
+    /// A wheel stalled and could not even back off.
+    Stall { wheel: Wheel, phase: Phase },
+    BatteryLow { volts: f32 },
+    /// Too many sensors have gone to carry on without them.
+    SensorsLost { sensors: &'static str, phase: Phase },
+    /// Every grasp missed the can. The claw is left open and raised.
+    CanMissed { attempts: u32 },
+    /// A command driven from the brick was run without its buttons.
+    ButtonsMissing { needed_by: &'static str, device: String },
+}
+
+pub type IcarusResult<T> = Result<T, IcarusError>;
+
+impl Display for IcarusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            IcarusError::Device {
+                device,
+                port,
+                operation,
+                phase,
+                source,
+            } => {
+                write!(f, "{}", device)?;
+                if let Some(port) = port {
+                    write!(f, " on {}", port)?;
+                }
+                write!(f, ": {} failed during {}: {:?}", operation, phase, source)
+            }
+            IcarusError::Timeout { motor, deadline, phase } => {
+                write!(f, "Motor {} did not finish within {:?} during {}", motor, deadline, phase)
+            }
+            IcarusError::Stationary { motor, phase } => write!(f, "Motor {} was asked to move at speed 0 during {}", motor, phase),
+            IcarusError::CalibrationMissing { phase } => write!(f, "{} needs calibrating first", phase),
+            IcarusError::ConfigInvalid(message) => write!(f, "{}", message),
+            IcarusError::Stall { wheel, phase } => {
+                write!(f, "{:?} wheel stalled and could not back off during {}", wheel, phase)
+            }
+            IcarusError::SensorsLost { sensors, phase } => write!(f, "Lost the {} during {}", sensors, phase),
+            IcarusError::BatteryLow { volts } => write!(f, "Battery at {:.2} V is too low to start, charge it first", volts),
+            IcarusError::CanMissed { attempts } => write!(f, "Gave up on the can after {} grasp attempts", attempts),
+            IcarusError::ButtonsMissing { needed_by, device } => write!(f, "{} needs the brick buttons on {}", needed_by, device),
+        };
+    }
+}
+
+impl IcarusError {
+    /// The attached devices could not be listed.
+    pub fn listing(source: Ev3Error) -> Self {
+        return IcarusError::Device {
+            device: "attached devices",
+            port: None,
+            operation: "list",
+            phase: Phase::current(),
+            source,
+        };
+    }
+
+    /// A device on `port` that could not be opened.
+    pub fn opening(device: &'static str, port: &str, source: Ev3Error) -> Self {
+        return IcarusError::Device {
+            device,
+            port: Some(port.to_string()),
+            operation: "open",
+            phase: Phase::current(),
+            source,
+        };
+    }
+}
+
+pub trait Context<T> {
+    /// Says which device failed doing what, e.g.
+    /// `self.left_light.get_rgb().context(&self.left_light, "left colour sensor", "read RGB")`.
+    fn context<D: Device>(self, device: &D, name: &'static str, operation: &'static str) -> IcarusResult<T>;
+}
+
+impl<T> Context<T> for Ev3Result<T> {
+    fn context<D: Device>(self, device: &D, name: &'static str, operation: &'static str) -> IcarusResult<T> {
+        return self.map_err(|source| IcarusError::Device {
+            device: name,
+            port: short_port(device).ok(),
+            operation,
+            phase: Phase::current(),
+            source,
+        });
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    process::Child,
+    sync::{Arc, Mutex},
+};
+
+use ev3dev_lang_rust::{sound, Ev3Result, Led};
+
+use crate::{line_follow::Side, mission::Phase, Icarus};
+
+/// Something worth telling the people standing around the course about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cue {
+    Phase(Phase),
+    GreenTurn(Side),
+    Detour,
+    Error,
+    LowBattery,
+}
+
+pub trait Indicator: Send {
+    fn signal(&mut self, cue: Cue);
+}
+
+// Not a OnceLock so a fake can be swapped in
+static INDICATOR: Mutex<Option<Box<dyn Indicator>>> = Mutex::new(None);
+
+/// Routes every cue from here on to `indicator`.
+pub fn install(indicator: Box<dyn Indicator>) {
+    if let Ok(mut installed) = INDICATOR.lock() {
+        *installed = Some(indicator);
+    }
+}
+
+/// Signals `cue` if an indicator is installed. Skipped rather than waited for if
+/// another thread is mid-cue, so the panic hook and signal handler can't hang on it.
+pub fn cue(cue: Cue) {
+    if let Ok(mut installed) = INDICATOR.try_lock() {
+        if let Some(indicator) = installed.as_mut() {
+            indicator.signal(cue);
+        }
+    }
+}
+
+/// The brick's two status LEDs and its beeper.
+pub struct Ev3Indicator {
+    led: Led,
+    sound: bool,
+    // Beeps run as child processes; kept so they can be reaped
+    playing: Vec<Child>,
+}
+
+impl Ev3Indicator {
+    pub fn new(sound: bool) -> Ev3Result<Self> {
+        return Ok(Self {
+            led: Led::new()?,
+            sound,
+            playing: Vec::new(),
+        });
+    }
+
+    fn show(&mut self, cue: Cue) -> Ev3Result<()> {
+        let (left, right, tones) = pattern(cue);
+        self.led.set_left_color(left)?;
+        self.led.set_right_color(right)?;
+
+        self.playing.retain_mut(|child| !matches!(child.try_wait(), Ok(Some(_)) | Err(_)));
+        if self.sound && !tones.is_empty() {
+            self.playing.push(sound::tone_sequence(tones)?);
+        }
+        return Ok(());
+    }
+}
+
+// (left, right) LED colours and (Hz, ms, ms pause after) tones
+type Pattern = ((u8, u8), (u8, u8), &'static [(f32, i32, i32)]);
+
+fn pattern(cue: Cue) -> Pattern {
+    return match cue {
+        Cue::Phase(Phase::Startup) => (Led::COLOR_AMBER, Led::COLOR_AMBER, &[]),
+        Cue::Phase(Phase::Calibration) => (Led::COLOR_ORANGE, Led::COLOR_ORANGE, &[(660., 100, 0)]),
+        Cue::Phase(Phase::LineFollow) => (Led::COLOR_GREEN, Led::COLOR_GREEN, &[]),
+        Cue::Phase(Phase::WaterTower) => (Led::COLOR_YELLOW, Led::COLOR_YELLOW, &[]),
+        Cue::Phase(Phase::ChemicalSpill) => (Led::COLOR_AMBER, Led::COLOR_GREEN, &[(880., 100, 50), (1320., 150, 0)]),
+        Cue::Phase(Phase::Shutdown) => (Led::COLOR_OFF, Led::COLOR_OFF, &[(440., 200, 0)]),
+        Cue::GreenTurn(Side::Left) => (Led::COLOR_GREEN, Led::COLOR_OFF, &[(1320., 60, 0)]),
+        Cue::GreenTurn(Side::Right) => (Led::COLOR_OFF, Led::COLOR_GREEN, &[(1760., 60, 0)]),
+        Cue::Detour => (Led::COLOR_YELLOW, Led::COLOR_YELLOW, &[(990., 80, 40), (990., 80, 0)]),
+        Cue::Error => (Led::COLOR_RED, Led::COLOR_RED, &[(220., 400, 0)]),
+        Cue::LowBattery => (Led::COLOR_RED, Led::COLOR_AMBER, &[(330., 150, 100), (262., 300, 0)]),
+    };
+}
+
+impl Indicator for Ev3Indicator {
+    fn signal(&mut self, cue: Cue) {
+        // Losing a cue is better than losing the run
+        if let Err(e) = self.show(cue) {
+            Icarus::debug(format!("Could not signal {:?}: {:?}", cue, e));
+        }
+    }
+}
+
+/// Keeps every cue instead of showing it; clones share the same record.
+#[derive(Clone, Default)]
+pub struct RecordingIndicator {
+    cues: Arc<Mutex<Vec<Cue>>>,
+}
+
+impl RecordingIndicator {
+    pub fn cues(&self) -> Vec<Cue> {
+        return self.cues.lock().map(|cues| cues.clone()).unwrap_or_default();
+    }
+}
+
+impl Indicator for RecordingIndicator {
+    fn signal(&mut self, cue: Cue) {
+        if let Ok(mut cues) = self.cues.lock() {
+            cues.push(cue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_light_their_leds() {
+        let phases = [Phase::Calibration, Phase::LineFollow, Phase::WaterTower, Phase::ChemicalSpill, Phase::Shutdown];
+        let leds: Vec<_> = phases
+            .into_iter()
+            .map(|phase| {
+                let (left, right, _) = pattern(Cue::Phase(phase));
+                (left, right)
+            })
+            .collect();
+        assert_eq!(
+            leds,
+            [
+                (Led::COLOR_ORANGE, Led::COLOR_ORANGE),
+                (Led::COLOR_GREEN, Led::COLOR_GREEN),
+                (Led::COLOR_YELLOW, Led::COLOR_YELLOW),
+                (Led::COLOR_AMBER, Led::COLOR_GREEN),
+                (Led::COLOR_OFF, Led::COLOR_OFF),
+            ]
+        );
+    }
+
+    #[test]
+    fn green_turns_light_their_side() {
+        let (left, right, _) = pattern(Cue::GreenTurn(Side::Left));
+        assert_eq!((left, right), (Led::COLOR_GREEN, Led::COLOR_OFF));
+        let (left, right, _) = pattern(Cue::GreenTurn(Side::Right));
+        assert_eq!((left, right), (Led::COLOR_OFF, Led::COLOR_GREEN));
+    }
+}
@@ -1,19 +1,39 @@
 use std::{
     fmt::Display,
     ops::{Add, Div},
+    str::FromStr,
     thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use ev3dev_lang_rust::Ev3Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    colour::{ColourClass, ColourClassifier},
+    indicator::{self, Cue},
+    line_sensing::{self, LinePosition, LineSample, DEFAULT_BLACK_FRACTION},
+    drive_monitor::{DriveEvent, DriveMonitor},
+    error::{Context, IcarusError, IcarusResult},
+    mission::{Phase, Resume},
+    remote::{RemoteAction, RunState},
+    sensors::OUT_OF_RANGE,
+    training::TrainedClassifier,
+    trace::{TraceEvent, TraceTick},
+    Icarus, LineFollowRobot,
+};
 
-use crate::{Icarus, LineFollowRobot};
+// Steps to each side of the calibration sweep
+const SWEEP_STEPS: u32 = 10;
 
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LineFollowParameters {
     pub kp: f32,
     pub tick: u64, // In ms
     pub targeted_speed: i32,
     pub green_threshold: f32,
+    /// Used instead of the above while following on one sensor
+    pub edge: EdgeParameters,
 }
 
 impl LineFollowParameters {
@@ -23,14 +43,110 @@ impl LineFollowParameters {
             tick,
             targeted_speed,
             green_threshold,
+            edge: EdgeParameters::default(),
+        };
+    }
+
+    pub fn get(&self, parameter: Parameter) -> f32 {
+        return match parameter {
+            Parameter::Kp => self.kp,
+            Parameter::Tick => self.tick as f32,
+            Parameter::TargetedSpeed => self.targeted_speed as f32,
+            Parameter::GreenThreshold => self.green_threshold,
+        };
+    }
+
+    pub fn set(&mut self, parameter: Parameter, value: f32) {
+        match parameter {
+            Parameter::Kp => self.kp = value,
+            Parameter::Tick => self.tick = value as u64,
+            Parameter::TargetedSpeed => self.targeted_speed = value as i32,
+            Parameter::GreenThreshold => self.green_threshold = value,
+        }
+    }
+}
+
+/// A line follow parameter that can be changed mid-run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parameter {
+    Kp,
+    Tick,
+    TargetedSpeed,
+    GreenThreshold,
+}
+
+impl Display for Parameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.write_str(match self {
+            Parameter::Kp => "kp",
+            Parameter::Tick => "tick",
+            Parameter::TargetedSpeed => "targeted_speed",
+            Parameter::GreenThreshold => "green_threshold",
+        });
+    }
+}
+
+impl FromStr for Parameter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "kp" => Ok(Parameter::Kp),
+            "tick" => Ok(Parameter::Tick),
+            "targeted_speed" => Ok(Parameter::TargetedSpeed),
+            "green_threshold" => Ok(Parameter::GreenThreshold),
+            _ => Err(format!("unknown parameter {}", s)),
         };
     }
 }
 
+/// Gains for following one edge of the line with a single colour sensor.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EdgeParameters {
+    pub kp: f32,
+    pub targeted_speed: i32,
+    /// Where to hold the sensor between the line's black (0) and the calibration surface (1)
+    pub edge_fraction: f32,
+}
+
+impl Default for EdgeParameters {
+    fn default() -> Self {
+        return Self {
+            kp: 1.5,
+            targeted_speed: 80,
+            edge_fraction: 0.5,
+        };
+    }
+}
+
+/// Which colour sensors line follow steers with.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FollowMode {
+    Both,
+    /// Only the left sensor, following the left edge of the line
+    Left,
+    /// Only the right sensor, following the right edge of the line
+    Right,
+}
+
+impl Default for LineFollowParameters {
+    fn default() -> Self {
+        return Self::new(3., 50, 100, 1.7);
+    }
+}
+
 #[derive(Clone)]
 pub struct CalibrationProfile {
-    left: RGB,
-    right: RGB,
+    pub left: RGB,
+    pub right: RGB,
+    /// The extra line sensors, in config order
+    pub extra: Vec<RGB>,
+    /// Reflectivity over the line for each sensor: left, right, then the extras
+    pub black: Vec<f32>,
+    /// Built from this calibration when colours are classified rather than thresholded
+    pub classifier: Option<ColourClassifier>,
 }
 
 impl From<(RGB, RGB)> for CalibrationProfile {
@@ -38,15 +154,33 @@ impl From<(RGB, RGB)> for CalibrationProfile {
         return Self {
             left: value.0,
             right: value.1,
+            extra: Vec::new(),
+            black: Vec::new(),
+            classifier: None,
         };
     }
 }
 
-#[derive(Clone)]
+impl CalibrationProfile {
+    /// Sensor `index`'s reflectivity over the line, estimated from its surface if it wasn't measured.
+    pub fn black(&self, index: usize) -> f32 {
+        if let Some(black) = self.black.get(index) {
+            return *black;
+        }
+        let surface = match index {
+            0 => &self.left,
+            1 => &self.right,
+            _ => &self.extra[index - 2],
+        };
+        return surface.reflectivity() * DEFAULT_BLACK_FRACTION;
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RGB {
-    r: i32,
-    g: i32,
-    b: i32,
+    pub r: i32,
+    pub g: i32,
+    pub b: i32,
 }
 
 impl RGB {
@@ -65,11 +199,11 @@ impl RGB {
         return readings;
     }
 
-    fn rb_ave(&self) -> i32 {
+    pub fn rb_ave(&self) -> i32 {
         return (self.r + self.b) / 2;
     }
 
-    fn reflectivity(&self) -> f32 {
+    pub fn reflectivity(&self) -> f32 {
         return 0.2125 * self.r as f32 + 0.7154 * self.g as f32 + 0.0721 * self.b as f32;
     }
 
@@ -92,13 +226,7 @@ impl From<(i32, i32, i32)> for RGB {
 impl Display for RGB {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         return f.write_str(
-            format!(
-                "R: {}, G: {}, B: {}",
-                self.r.to_string(),
-                self.g.to_string(),
-                self.b.to_string()
-            )
-            .as_str(),
+            format!("R: {}, G: {}, B: {}", self.r, self.g, self.b).as_str(),
         );
     }
 }
@@ -127,311 +255,586 @@ impl Div for RGB {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// What the controller wants done with one tick's colour readings.
+pub struct Steering {
+    pub green_turn: Option<Side>,
+    pub left_calibrated: RGB,
+    pub right_calibrated: RGB,
+    pub heading: f32,
+    /// Where the line was seen, for whatever wants to know if it is there at all
+    pub position: LinePosition,
+    pub left_speed: i32,
+    pub right_speed: i32,
+}
+
+/// The decision making half of `line_follow()`, kept free of hardware so recorded
+/// traces can be fed back through it offline.
+#[derive(Default)]
+pub struct LineFollowController {
+    green_timeout: u32,
+    // Ticks left to run at reduced speed after a wheel slipped
+    slip_recovery: u32,
+    // Where the sensor array last saw the line, to keep turning towards it once it is lost
+    last_heading: f32,
+}
+
+impl LineFollowController {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn sees_water_tower(&self, ultrasonic_reading: f32) -> bool {
+        return ultrasonic_reading < 15.;
+    }
+
+    pub fn step(
+        &mut self,
+        params: &LineFollowParameters,
+        profile: &CalibrationProfile,
+        left_reading: &RGB,
+        right_reading: &RGB,
+    ) -> Steering {
+        let green_turn = self.green(params, profile, left_reading, right_reading);
+
+        // Only which side each sensor is on matters for a pair
+        let samples = line_sensing::samples(&[-1., 1.], profile, left_reading, right_reading, &[]);
+        let position = line_sensing::estimate(&samples);
+        let heading = 100. * position.offset;
+
+        let (left_reading, right_reading) =
+            RGB::calibrated((left_reading.clone(), right_reading.clone()), profile);
+        let (left_speed, right_speed) = self.drive(params, heading);
+        return Steering {
+            green_turn,
+            left_calibrated: left_reading,
+            right_calibrated: right_reading,
+            heading,
+            position,
+            left_speed,
+            right_speed,
+        };
+    }
+
+    /// Steers on the line position across every downward sensor (see `line_sensing`).
+    /// Green turns are still looked for on the left and right sensors.
+    pub fn step_array(
+        &mut self,
+        params: &LineFollowParameters,
+        profile: &CalibrationProfile,
+        left_reading: &RGB,
+        right_reading: &RGB,
+        samples: &[LineSample],
+    ) -> Steering {
+        let green_turn = self.green(params, profile, left_reading, right_reading);
+
+        let position = line_sensing::estimate(samples);
+        if position.sees_line() {
+            self.last_heading = 100. * position.offset;
+        }
+        let heading = self.last_heading;
+
+        let (left_reading, right_reading) =
+            RGB::calibrated((left_reading.clone(), right_reading.clone()), profile);
+        let (left_speed, right_speed) = self.drive(params, heading);
+        return Steering {
+            green_turn,
+            left_calibrated: left_reading,
+            right_calibrated: right_reading,
+            heading,
+            position,
+            left_speed,
+            right_speed,
+        };
+    }
+
+    fn green(
+        &mut self,
+        params: &LineFollowParameters,
+        profile: &CalibrationProfile,
+        left_reading: &RGB,
+        right_reading: &RGB,
+    ) -> Option<Side> {
+        let green_left = is_green(params, profile, left_reading, &profile.left);
+        let green_right = is_green(params, profile, right_reading, &profile.right);
+
+        let mut green_turn = None;
+        if green_left && self.green_timeout > 100 {
+            green_turn = Some(Side::Left);
+            self.green_timeout = 0;
+        }
+        if green_right && self.green_timeout > 100 {
+            green_turn = Some(Side::Right);
+            self.green_timeout = 0;
+        }
+        self.green_timeout += 1;
+        return green_turn;
+    }
+
+    // Wheel speeds for a heading, positive turning right
+    fn drive(&mut self, params: &LineFollowParameters, heading: f32) -> (i32, i32) {
+        // let left_motor_speed = self.parameters.targeted_speed - (heading / 100.) as i32 * self.parameters.targeted_speed;
+        // let right_motor_speed = self.parameters.targeted_speed + (heading / 100.) as i32 * self.parameters.targeted_speed;
+
+        let left_motor_speed =
+            (params.targeted_speed as f32) + ((params.kp * heading / 100.) * (params.targeted_speed as f32));
+
+        let right_motor_speed =
+            (params.targeted_speed as f32) - ((params.kp * heading / 300.) * (params.targeted_speed as f32));
+
+        return self.limit(left_motor_speed, right_motor_speed);
+    }
+
+    /// Steers along one edge of the line with only the `sensor` side's colour sensor,
+    /// holding it `edge_fraction` of the way from the line's black to the calibration
+    /// surface. The left sensor follows the left edge and the right sensor the right one.
+    pub fn step_edge(
+        &mut self,
+        params: &LineFollowParameters,
+        profile: &CalibrationProfile,
+        sensor: Side,
+        reading: &RGB,
+    ) -> Steering {
+        let edge = &params.edge;
+        let (surface, index) = match sensor {
+            Side::Left => (&profile.left, 0),
+            Side::Right => (&profile.right, 1),
+        };
+
+        let mut green_turn = None;
+        if is_green(params, profile, reading, surface) && self.green_timeout > 100 {
+            green_turn = Some(sensor);
+            self.green_timeout = 0;
+        }
+
+        let sample = LineSample {
+            offset_mm: 0.,
+            reading: reading.clone(),
+            surface: surface.clone(),
+            black: profile.black(index),
+        };
+        // Percent of the black to surface range we are off the edge, towards the surface
+        let error = 100. * (1. - sample.darkness() - edge.edge_fraction);
+        // Too bright means the line is further in, which is right of the left sensor and left of the right one
+        let heading = match sensor {
+            Side::Left => error,
+            Side::Right => -error,
+        };
+
+        let turn = (edge.kp * heading / 100.) * edge.targeted_speed as f32;
+        let (left_speed, right_speed) = self.limit(edge.targeted_speed as f32 + turn, edge.targeted_speed as f32 - turn);
+        self.green_timeout += 1;
+
+        let calibrated = RGB::from((reading.r - surface.r, reading.g - surface.g, reading.b - surface.b));
+        let (left_calibrated, right_calibrated) = match sensor {
+            Side::Left => (calibrated, RGB::from((0, 0, 0))),
+            Side::Right => (RGB::from((0, 0, 0)), calibrated),
+        };
+        return Steering {
+            green_turn,
+            left_calibrated,
+            right_calibrated,
+            heading,
+            position: LinePosition {
+                offset: (heading / 100.).clamp(-1., 1.),
+                confidence: sample.darkness(),
+            },
+            left_speed,
+            right_speed,
+        };
+    }
+
+    // Slip recovery and the speed limit, common to both ways of steering
+    fn limit(&mut self, mut left_motor_speed: f32, mut right_motor_speed: f32) -> (i32, i32) {
+        // Ease off to regain traction
+        if self.slip_recovery > 0 {
+            left_motor_speed /= 2.;
+            right_motor_speed /= 2.;
+            self.slip_recovery -= 1;
+        }
+
+        // Guard for max speed
+        if left_motor_speed.abs() >= 800. {
+            left_motor_speed = left_motor_speed.signum() * 800.;
+        }
+        if right_motor_speed.abs() >= 800. {
+            right_motor_speed = right_motor_speed.signum() * 800.;
+        }
+
+        return (left_motor_speed as i32, right_motor_speed as i32);
+    }
+
+    pub fn on_slip(&mut self) {
+        self.slip_recovery = 20;
+    }
+}
+
+// By the calibration's classifier if it has one, or else by green outweighing red and blue
+fn is_green(params: &LineFollowParameters, profile: &CalibrationProfile, reading: &RGB, surface: &RGB) -> bool {
+    return match &profile.classifier {
+        Some(classifier) => classifier.classify(reading, surface).class == ColourClass::Green,
+        None => reading.g as f32 > params.green_threshold * reading.rb_ave() as f32,
+    };
+}
+
 impl LineFollowRobot {
-    pub fn calibrate(&mut self) -> Ev3Result<()> {
+    pub fn calibrate(&mut self) -> IcarusResult<()> {
+        Phase::Calibration.enter();
+        if let Some(battery) = &mut self.battery {
+            if let Err(volts) = battery.start_run()? {
+                return Err(IcarusError::BatteryLow { volts });
+            }
+        }
         Icarus::info("Calibrating in 3 seconds".to_string());
         Icarus::info("Abort program to avert calibration".to_string());
-        self.left_light.set_mode_rgb_raw()?;
-        self.right_light.set_mode_rgb_raw()?;
+        self.left_light.set_mode_rgb_raw().context(&self.left_light, "left colour sensor", "set RGB mode")?;
+        self.right_light.set_mode_rgb_raw().context(&self.right_light, "right colour sensor", "set RGB mode")?;
+        for light in &self.extra_lights {
+            light.set_mode_rgb_raw().context(light, "extra colour sensor", "set RGB mode")?;
+        }
 
         sleep(Duration::from_secs(3));
 
         let mut left_rgb = RGB::from((0, 0, 0));
         let mut right_rgb = RGB::from((0, 0, 0));
+        let mut extra_rgb = vec![RGB::from((0, 0, 0)); self.extra_lights.len()];
 
         for _ in 0..100 {
             sleep(Duration::from_millis(10));
-            left_rgb = left_rgb.add(RGB::from(self.left_light.get_rgb()?));
-            right_rgb = right_rgb.add(RGB::from(self.right_light.get_rgb()?));
+            let left = self.left_light.get_rgb().context(&self.left_light, "left colour sensor", "read RGB")?;
+            let right = self.right_light.get_rgb().context(&self.right_light, "right colour sensor", "read RGB")?;
+            left_rgb = left_rgb.add(RGB::from(left));
+            right_rgb = right_rgb.add(RGB::from(right));
+            for (light, sum) in self.extra_lights.iter().zip(extra_rgb.iter_mut()) {
+                let extra = light.get_rgb().context(light, "extra colour sensor", "read RGB")?;
+                *sum = sum.clone().add(RGB::from(extra));
+            }
         }
 
         left_rgb = left_rgb.div(RGB::from((100, 100, 100)));
         right_rgb = right_rgb.div(RGB::from((100, 100, 100)));
 
-        let calibration = CalibrationProfile::from((left_rgb, right_rgb));
+        let mut calibration = CalibrationProfile::from((left_rgb, right_rgb));
+        calibration.extra = extra_rgb.into_iter().map(|sum| sum.div(RGB::from((100, 100, 100)))).collect();
+        let surfaces: Vec<f32> = [&calibration.left, &calibration.right]
+            .into_iter()
+            .chain(&calibration.extra)
+            .map(|surface| surface.reflectivity())
+            .collect();
+        calibration.black = if self.line_sensing.black_sweep_rotations > 0. {
+            self.sweep_black(&surfaces)?
+        } else {
+            surfaces.iter().map(|white| white * self.line_sensing.black_fraction).collect()
+        };
         Icarus::info(format!(
             "Calibration completed! Left: {}, Right: {}",
             calibration.left, calibration.right
         ));
+        for (sensor, rgb) in self.line_sensing.extra.iter().zip(&calibration.extra) {
+            Icarus::info(format!("Extra sensor on {}: {}", sensor.port, rgb));
+        }
+        Icarus::info(format!("Line black per sensor: {:?}", calibration.black));
+        if self.colour.classify_green {
+            calibration.classifier = Some(self.classifier(&calibration));
+        }
         self.calibration = Some(calibration);
+        self.restart_trace();
 
         Ok(())
     }
 
-    pub fn line_follow(&mut self) -> Ev3Result<()> {
-        self.ultrasonic.set_mode_us_dist_cm()?;
+    // Pivots left, right and back to the start a step at a time, keeping the darkest each
+    // sensor reads. One that never got much darker than its `surfaces` probably missed the line.
+    fn sweep_black(&self, surfaces: &[f32]) -> IcarusResult<Vec<f32>> {
+        let step = self.line_sensing.black_sweep_rotations / SWEEP_STEPS as f32;
+        let speed = self.parameters.targeted_speed;
+        let mut darkest = surfaces.to_vec();
+        for (turn, steps) in [(-1., SWEEP_STEPS), (1., 2 * SWEEP_STEPS), (-1., SWEEP_STEPS)] {
+            for _ in 0..steps {
+                self.drive_rotations(turn * step, -turn * step, speed * turn as i32, -speed * turn as i32)?;
+                for (black, reading) in darkest.iter_mut().zip(self.read_line_sensors()?) {
+                    *black = black.min(reading.reflectivity());
+                }
+            }
+        }
+
+        for (index, (black, white)) in darkest.iter_mut().zip(surfaces).enumerate() {
+            if *black > white * 0.5 {
+                Icarus::warn(format!("Line sensor {} saw no line in the sweep, guessing its black", index));
+                *black = white * self.line_sensing.black_fraction;
+            }
+        }
+        return Ok(darkest);
+    }
+
+    // The trained classifier if there is one, or else one from the configured colours
+    fn classifier(&self, calibration: &CalibrationProfile) -> ColourClassifier {
+        let Some(path) = &self.colour.trained else {
+            return ColourClassifier::from_calibration(&self.colour, calibration);
+        };
+        match TrainedClassifier::load(path) {
+            Ok(trained) => {
+                // Lab is taken relative to the surface, but a big change in lighting still shifts the colours
+                let change = calibration.left.reflectivity() / trained.surface.reflectivity().max(1.);
+                if !(0.8..=1.25).contains(&change) {
+                    Icarus::warn(format!("The mat reads {:.0}% of what it did in training, consider retraining", change * 100.));
+                }
+                Icarus::info(format!("Classifying colours as trained on {} samples", trained.samples));
+                return trained.classifier;
+            }
+            Err(e) => {
+                Icarus::warn(format!("Trained colours unavailable, using the configured ones: {}", e));
+                return ColourClassifier::from_calibration(&self.colour, calibration);
+            }
+        }
+    }
+
+    /// Every downward sensor, straight from the hardware: left, right, then the extras.
+    pub fn read_line_sensors(&self) -> IcarusResult<Vec<RGB>> {
+        let mut readings = vec![
+            RGB::from(self.left_light.get_rgb().context(&self.left_light, "left colour sensor", "read RGB")?),
+            RGB::from(self.right_light.get_rgb().context(&self.right_light, "right colour sensor", "read RGB")?),
+        ];
+        for light in &self.extra_lights {
+            readings.push(RGB::from(light.get_rgb().context(light, "extra colour sensor", "read RGB")?));
+        }
+        return Ok(readings);
+    }
+
+    pub fn line_follow(&mut self) -> IcarusResult<()> {
+        Phase::LineFollow.enter();
+        self.ultrasonic.set_mode_us_dist_cm().context(&self.ultrasonic, "ultrasonic", "set distance mode")?;
         if let Some(profile) = &self.calibration.clone() {
-            let mut green_timeout = 0;
+            let mut controller = LineFollowController::new();
+            let mut drive_monitor = DriveMonitor::new();
+            let mut last_tick = Instant::now();
+            let mut edge_following = None;
+            let offsets = self.line_sensing.offsets();
             loop {
+                let resume = match self.service_remote(RunState::Running)? {
+                    RemoteAction::Stop => {
+                        self.stop_drive()?;
+                        Icarus::info("Line follow stopped remotely".to_string());
+                        return Ok(());
+                    }
+                    RemoteAction::Pause => self.pause()?,
+                    RemoteAction::Start(_) | RemoteAction::Resume(_) | RemoteAction::Continue => self.poll_pause()?,
+                };
+                match resume {
+                    Resume::Continue => {}
+                    // Picked up again by run_course
+                    Resume::From(phase) => {
+                        self.resume_from = Some(phase);
+                        return Ok(());
+                    }
+                    Resume::Abort => {
+                        Icarus::info("Line follow aborted".to_string());
+                        return Ok(());
+                    }
+                }
+
+                let loop_time = last_tick.elapsed();
+                last_tick = Instant::now();
+                let mut events = Vec::<TraceEvent>::new();
+
                 // Water tower
                 // Ideally we want to make a _/‾‾‾‾\_ shape
-                let ultrasonic_reading = self.ultrasonic.get_distance_centimeters()?;
-                if ultrasonic_reading < 15. {
+                // Without the ultrasonic there is no water tower to see
+                let ultrasonic_reading = self.read_distance()?.unwrap_or(OUT_OF_RANGE);
+                if controller.sees_water_tower(ultrasonic_reading) {
                     Icarus::info("Avoiding water tower".to_string());
-                    self.avoid_water_tower()?;
+                    events.push(TraceEvent::WaterTower);
+                    indicator::cue(Cue::Detour);
+                    let detour = self.avoid_water_tower();
+                    self.recover(detour)?;
                 }
 
-                let left_reading = RGB::from(self.left_light.get_rgb()?);
-                let right_reading = RGB::from(self.right_light.get_rgb()?);
-
-                let green_left = left_reading.g as f32
-                    > self.parameters.green_threshold * left_reading.rb_ave() as f32;
-                let green_right = right_reading.g as f32
-                    > self.parameters.green_threshold * right_reading.rb_ave() as f32;
+                let left = match self.follow_mode {
+                    FollowMode::Right => None,
+                    _ => self.read_colour(Side::Left)?,
+                };
+                let right = match self.follow_mode {
+                    FollowMode::Left => None,
+                    _ => self.read_colour(Side::Right)?,
+                };
+                // With one colour sensor gone (or not wanted) the other follows an edge of the line on
+                // its own. The missing side is traced as the calibration surface.
+                let edge_sensor = match (&left, &right) {
+                    (Some(_), Some(_)) => None,
+                    (Some(_), None) => Some(Side::Left),
+                    (None, Some(_)) => Some(Side::Right),
+                    (None, None) => {
+                        self.stop_drive()?;
+                        return Err(IcarusError::SensorsLost { sensors: "colour sensors", phase: Phase::LineFollow });
+                    }
+                };
+                if edge_sensor != edge_following {
+                    match edge_sensor {
+                        Some(side) => Icarus::warn(format!("Edge following on the {:?} sensor", side)),
+                        None => Icarus::info("Back to following on both sensors".to_string()),
+                    }
+                    edge_following = edge_sensor;
+                }
+                let left_reading = left.unwrap_or_else(|| profile.left.clone());
+                let right_reading = right.unwrap_or_else(|| profile.right.clone());
+                let mut extra_readings = Vec::with_capacity(self.extra_lights.len());
+                for index in 0..self.extra_lights.len() {
+                    extra_readings.push(self.read_extra(index)?);
+                }
+                if let Some(battery) = &mut self.battery {
+                    battery.check();
+                }
+                let parameters = self.effective_parameters();
+                let steering = match edge_sensor {
+                    Some(Side::Left) => controller.step_edge(&parameters, profile, Side::Left, &left_reading),
+                    Some(Side::Right) => controller.step_edge(&parameters, profile, Side::Right, &right_reading),
+                    None if extra_readings.is_empty() => controller.step(&parameters, profile, &left_reading, &right_reading),
+                    None => {
+                        let samples = line_sensing::samples(&offsets, profile, &left_reading, &right_reading, &extra_readings);
+                        controller.step_array(&parameters, profile, &left_reading, &right_reading, &samples)
+                    }
+                };
+                if let Some(side) = edge_sensor {
+                    events.push(TraceEvent::Edge(side));
+                }
 
                 let bump_rotations = 0.8;
 
-                if green_left && green_timeout > 100 {
+                if let Some(side) = steering.green_turn {
                     Icarus::info(format!(
-                        "Detected green turn on the left {:?} margin",
+                        "Detected green turn on the {:?} {:?} margin",
+                        side,
                         left_reading.g as f32 / (1.75 * left_reading.rb_ave() as f32)
                     ));
+                    events.push(TraceEvent::Green(side));
+                    indicator::cue(Cue::GreenTurn(side));
 
                     // Stop
-                    self.left_motor.stop()?;
-                    self.right_motor.stop()?;
-
-                    // Bump
-                    self.left_motor.set_position_sp(
-                        (self.left_motor.get_count_per_rot()? as f32 * bump_rotations) as i32,
-                    )?;
-                    self.right_motor.set_position_sp(
-                        (self.right_motor.get_count_per_rot()? as f32 * bump_rotations) as i32,
-                    )?;
-                    self.left_motor
-                        .set_speed_sp(self.parameters.targeted_speed)?;
-                    self.right_motor
-                        .set_speed_sp(self.parameters.targeted_speed)?;
-                    self.left_motor.run_to_rel_pos(None)?;
-                    self.right_motor.run_to_rel_pos(None)?;
-                    #[cfg(target_os = "linux")]
-                    self.right_motor.wait_until_not_moving(None);
-                    #[cfg(target_os = "linux")]
-                    self.left_motor.wait_until_not_moving(None);
-
-                    // Turn
-                    self.left_motor.set_position_sp(
-                        -(self.left_motor.get_count_per_rot()? as f32 * 0.5) as i32,
-                    )?;
-                    self.right_motor.set_position_sp(
-                        (self.right_motor.get_count_per_rot()? as f32 * 0.9) as i32,
-                    )?;
-                    self.left_motor
-                        .set_speed_sp(-self.parameters.targeted_speed)?;
-                    self.right_motor
-                        .set_speed_sp(self.parameters.targeted_speed)?;
-                    self.left_motor.run_to_rel_pos(None)?;
-                    self.right_motor.run_to_rel_pos(None)?;
-                    #[cfg(target_os = "linux")]
-                    self.right_motor.wait_until_not_moving(None);
-                    #[cfg(target_os = "linux")]
-                    self.left_motor.wait_until_not_moving(None);
-                    green_timeout = 0;
+                    self.stop_drive()?;
+
+                    let turn = match side {
+                        Side::Left => self.green_turn(bump_rotations, -0.5, 0.9),
+                        Side::Right => self.green_turn(bump_rotations, 0.9, -0.5),
+                    };
+                    self.recover(turn)?;
+                    // Back to the line follow colours
+                    indicator::cue(Cue::Phase(Phase::LineFollow));
                 }
 
-                if green_right && green_timeout > 100 {
-                    Icarus::info(format!(
-                        "Detected green turn on the right {:?} margin",
-                        left_reading.g as f32 / (1.75 * left_reading.rb_ave() as f32)
-                    ));
-
-                    // Stop
-                    self.left_motor.stop()?;
-                    self.right_motor.stop()?;
-
-                    // Bump
-                    self.left_motor.set_position_sp(
-                        (self.left_motor.get_count_per_rot()? as f32 * bump_rotations) as i32,
-                    )?;
-                    self.right_motor.set_position_sp(
-                        (self.right_motor.get_count_per_rot()? as f32 * bump_rotations) as i32,
-                    )?;
-                    self.left_motor
-                        .set_speed_sp(self.parameters.targeted_speed)?;
-                    self.right_motor
-                        .set_speed_sp(self.parameters.targeted_speed)?;
-                    self.left_motor.run_to_rel_pos(None)?;
-                    self.right_motor.run_to_rel_pos(None)?;
-                    #[cfg(target_os = "linux")]
-                    self.right_motor.wait_until_not_moving(None);
-                    #[cfg(target_os = "linux")]
-                    self.left_motor.wait_until_not_moving(None);
-
-                    // Turn
-                    self.left_motor.set_position_sp(
-                        (self.left_motor.get_count_per_rot()? as f32 * 0.9) as i32,
-                    )?;
-                    self.right_motor.set_position_sp(
-                        -(self.right_motor.get_count_per_rot()? as f32 * 0.5) as i32,
-                    )?;
-                    self.left_motor
-                        .set_speed_sp(self.parameters.targeted_speed)?;
-                    self.right_motor
-                        .set_speed_sp(-self.parameters.targeted_speed)?;
-                    self.left_motor.run_to_rel_pos(None)?;
-                    self.right_motor.run_to_rel_pos(None)?;
-                    #[cfg(target_os = "linux")]
-                    self.right_motor.wait_until_not_moving(None);
-                    #[cfg(target_os = "linux")]
-                    self.left_motor.wait_until_not_moving(None);
-                    green_timeout = 0;
+                let tick = Some(Duration::from_millis(parameters.tick));
+                self.left_motor.set_speed_sp(steering.left_speed).context(&self.left_motor, "left motor", "set speed")?;
+                self.right_motor.set_speed_sp(steering.right_speed).context(&self.right_motor, "right motor", "set speed")?;
+                self.left_motor.run_timed(tick).context(&self.left_motor, "left motor", "run")?;
+                self.right_motor.run_timed(tick).context(&self.right_motor, "right motor", "run")?;
+
+                let drive_event = self.check_drive(&mut drive_monitor)?;
+                match drive_event {
+                    Some(DriveEvent::Stall(_)) => events.push(TraceEvent::Stall),
+                    Some(DriveEvent::Slip(_)) => events.push(TraceEvent::Slip),
+                    None => {}
                 }
 
-                let (left_reading, right_reading) =
-                    RGB::calibrated((left_reading, right_reading), profile);
-                let heading = left_reading.reflectivity() - right_reading.reflectivity();
-
-                // let left_motor_speed = self.parameters.targeted_speed - (heading / 100.) as i32 * self.parameters.targeted_speed;
-                // let right_motor_speed = self.parameters.targeted_speed + (heading / 100.) as i32 * self.parameters.targeted_speed;
+                if self.trace.is_some() || self.telemetry.is_some() {
+                    let tick = TraceTick {
+                        phase: Phase::current(),
+                        events,
+                        left_raw: left_reading,
+                        right_raw: right_reading,
+                        extra_raw: extra_readings,
+                        left_calibrated: steering.left_calibrated,
+                        right_calibrated: steering.right_calibrated,
+                        heading: steering.heading,
+                        left_speed: steering.left_speed,
+                        right_speed: steering.right_speed,
+                        ultrasonic: ultrasonic_reading,
+                        left_position: self.left_motor.get_position().context(&self.left_motor, "left motor", "read position")?,
+                        right_position: self.right_motor.get_position().context(&self.right_motor, "right motor", "read position")?,
+                    };
+                    if let Some(telemetry) = &mut self.telemetry {
+                        telemetry.publish(&tick, loop_time);
+                    }
+                    if let Some(Err(e)) = self.trace.as_mut().map(|trace| trace.record(&tick)) {
+                        Icarus::warn(format!("Trace write failed, no longer recording: {}", e));
+                        self.trace = None;
+                    }
+                }
 
-                let mut left_motor_speed = (self.parameters.targeted_speed as f32)
-                    + ((self.parameters.kp * heading / 100.)
-                        * (self.parameters.targeted_speed as f32));
+                match drive_event {
+                    Some(DriveEvent::Stall(wheel)) => self.free_stall(wheel)?,
+                    Some(DriveEvent::Slip(_)) => controller.on_slip(),
+                    None => {}
+                }
+            }
+        } else {
+            return Err(IcarusError::CalibrationMissing { phase: Phase::LineFollow });
+        }
+    }
 
-                let mut right_motor_speed = (self.parameters.targeted_speed as f32)
-                    - ((self.parameters.kp * heading / 300.)
-                        * (self.parameters.targeted_speed as f32));
+    // Bumps forward onto the junction and then turns by the given wheel rotations
+    fn green_turn(&self, bump_rotations: f32, left_rotations: f32, right_rotations: f32) -> IcarusResult<()> {
+        let speed = self.parameters.targeted_speed;
 
-                // Guard for max speed
-                if left_motor_speed.abs() >= 800. {
-                    left_motor_speed = left_motor_speed.signum() * 800.;
-                }
-                if right_motor_speed.abs() >= 800. {
-                    right_motor_speed = right_motor_speed.signum() * 800.;
-                }
+        // Bump
+        self.drive_rotations(bump_rotations, bump_rotations, speed, speed)?;
 
-                self.left_motor.set_speed_sp(left_motor_speed as i32)?;
-                self.right_motor.set_speed_sp(right_motor_speed as i32)?;
-                self.left_motor
-                    .run_timed(Some(Duration::from_millis(self.parameters.tick)))
-                    .unwrap();
-                self.right_motor
-                    .run_timed(Some(Duration::from_millis(self.parameters.tick)))
-                    .unwrap();
+        // Turn
+        return self.drive_rotations(
+            left_rotations,
+            right_rotations,
+            speed * left_rotations.signum() as i32,
+            speed * right_rotations.signum() as i32,
+        );
+    }
 
-                green_timeout += 1;
-            }
-        } else {
-            Icarus::warn("Calibration is required before line follow can be executed".to_string());
+    // A manoeuvre that ran out of time is abandoned: back off and let line follow pick the line up again
+    pub fn recover(&self, result: IcarusResult<()>) -> IcarusResult<()> {
+        if let Err(IcarusError::Timeout { .. }) = &result {
+            Icarus::warn(format!("{}, backing off", result.unwrap_err()));
+            return self.back_off();
         }
+        return result;
+    }
 
-        Ok(())
+    pub fn avoid_water_tower(&self) -> IcarusResult<()> {
+        Phase::WaterTower.enter();
+        let detour = self.water_tower_detour();
+        // Back to line follow even when a leg fails, so the back off and whatever follows aren't logged as the detour
+        Phase::LineFollow.enter();
+        if detour.is_ok() {
+            Icarus::info("Returning to line follow".to_string());
+        }
+        return detour;
     }
 
-    pub fn avoid_water_tower(&self) -> Ev3Result<()> {
+    fn water_tower_detour(&self) -> IcarusResult<()> {
         let pivot_rotations = 0.3;
         let short_rotations = 1.4;
         let long_rotations = 0.4;
+        let speed = self.parameters.targeted_speed;
 
         // Turn [_ -> /]
-        self.left_motor.set_position_sp(
-            -(self.left_motor.get_count_per_rot()? as f32 * pivot_rotations) as i32,
-        )?;
-        self.right_motor.set_position_sp(
-            (self.right_motor.get_count_per_rot()? as f32 * pivot_rotations) as i32,
-        )?;
-        self.left_motor
-            .set_speed_sp(-self.parameters.targeted_speed)?;
-        self.right_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.left_motor.run_to_rel_pos(None)?;
-        self.right_motor.run_to_rel_pos(None)?;
-        #[cfg(target_os = "linux")]
-        self.right_motor.wait_until_not_moving(None);
-        #[cfg(target_os = "linux")]
-        self.left_motor.wait_until_not_moving(None);
+        self.drive_rotations(-pivot_rotations, pivot_rotations, -speed, speed)?;
 
         // Move [/]
-        self.left_motor.set_position_sp(
-            (self.left_motor.get_count_per_rot()? as f32 * short_rotations) as i32,
-        )?;
-        self.right_motor.set_position_sp(
-            (self.right_motor.get_count_per_rot()? as f32 * short_rotations) as i32,
-        )?;
-        self.left_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.right_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.left_motor.run_to_rel_pos(None)?;
-        self.right_motor.run_to_rel_pos(None)?;
-        #[cfg(target_os = "linux")]
-        self.right_motor.wait_until_not_moving(None);
-        #[cfg(target_os = "linux")]
-        self.left_motor.wait_until_not_moving(None);
+        self.drive_rotations(short_rotations, short_rotations, speed, speed)?;
 
         // Turn [/ -> ‾]
-        self.left_motor.set_position_sp(
-            (self.left_motor.get_count_per_rot()? as f32 * pivot_rotations) as i32,
-        )?;
-        self.right_motor.set_position_sp(
-            -(self.right_motor.get_count_per_rot()? as f32 * pivot_rotations) as i32,
-        )?;
-        self.left_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.right_motor
-            .set_speed_sp(-self.parameters.targeted_speed)?;
-        self.left_motor.run_to_rel_pos(None)?;
-        self.right_motor.run_to_rel_pos(None)?;
-        #[cfg(target_os = "linux")]
-        self.right_motor.wait_until_not_moving(None);
-        #[cfg(target_os = "linux")]
-        self.left_motor.wait_until_not_moving(None);
+        self.drive_rotations(pivot_rotations, -pivot_rotations, speed, -speed)?;
 
         // Move [‾‾‾‾]
-        self.left_motor.set_position_sp(
-            (self.left_motor.get_count_per_rot()? as f32 * long_rotations) as i32,
-        )?;
-        self.right_motor.set_position_sp(
-            (self.right_motor.get_count_per_rot()? as f32 * long_rotations) as i32,
-        )?;
-        self.left_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.right_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.left_motor.run_to_rel_pos(None)?;
-        self.right_motor.run_to_rel_pos(None)?;
-        #[cfg(target_os = "linux")]
-        self.right_motor.wait_until_not_moving(None);
-        #[cfg(target_os = "linux")]
-        self.left_motor.wait_until_not_moving(None);
+        self.drive_rotations(long_rotations, long_rotations, speed, speed)?;
 
         // Turn [‾ -> \]
-        self.left_motor.set_position_sp(
-            (self.left_motor.get_count_per_rot()? as f32 * pivot_rotations) as i32,
-        )?;
-        self.right_motor.set_position_sp(
-            -(self.right_motor.get_count_per_rot()? as f32 * pivot_rotations) as i32,
-        )?;
-        self.left_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.right_motor
-            .set_speed_sp(-self.parameters.targeted_speed)?;
-        self.left_motor.run_to_rel_pos(None)?;
-        self.right_motor.run_to_rel_pos(None)?;
-        #[cfg(target_os = "linux")]
-        self.right_motor.wait_until_not_moving(None);
-        #[cfg(target_os = "linux")]
-        self.left_motor.wait_until_not_moving(None);
+        self.drive_rotations(pivot_rotations, -pivot_rotations, speed, -speed)?;
 
         // Move [\]
-        self.left_motor.set_position_sp(
-            (self.left_motor.get_count_per_rot()? as f32 * short_rotations) as i32,
-        )?;
-        self.right_motor.set_position_sp(
-            (self.right_motor.get_count_per_rot()? as f32 * short_rotations) as i32,
-        )?;
-        self.left_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.right_motor
-            .set_speed_sp(self.parameters.targeted_speed)?;
-        self.left_motor.run_to_rel_pos(None)?;
-        self.right_motor.run_to_rel_pos(None)?;
-        #[cfg(target_os = "linux")]
-        self.right_motor.wait_until_not_moving(None);
-        #[cfg(target_os = "linux")]
-        self.left_motor.wait_until_not_moving(None);
-
-        Icarus::info("Returning to line follow".to_string());
+        self.drive_rotations(short_rotations, short_rotations, speed, speed)?;
+
         return Ok(());
     }
 }
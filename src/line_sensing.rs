@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+
+use crate::line_follow::{CalibrationProfile, RGB};
+
+// Less darkness than this on every sensor and there is no line to place
+const MIN_CONFIDENCE: f32 = 0.1;
+
+/// The line's reflectivity as a fraction of the surface's, for sensors not calibrated on it.
+pub const DEFAULT_BLACK_FRACTION: f32 = 0.15;
+
+/// A downward colour sensor beyond the left/right pair, e.g. one in the centre.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LineSensorConfig {
+    pub port: String,
+    /// Sideways from the robot's centre line, right positive
+    pub offset_mm: f32,
+}
+
+impl Default for LineSensorConfig {
+    fn default() -> Self {
+        return Self {
+            port: "in4".to_string(),
+            offset_mm: 0.,
+        };
+    }
+}
+
+/// Where the downward sensors sit across the robot.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LineSensingConfig {
+    pub left_offset_mm: f32,
+    pub right_offset_mm: f32,
+    /// More sensors to place the line with; steering switches to the weighted position when there are any
+    pub extra: Vec<LineSensorConfig>,
+    /// Wheel rotations to pivot each way after calibrating, to read the line's black under every
+    /// sensor. 0 skips the sweep and takes black as `black_fraction` of the surface.
+    pub black_sweep_rotations: f32,
+    pub black_fraction: f32,
+}
+
+impl Default for LineSensingConfig {
+    fn default() -> Self {
+        return Self {
+            left_offset_mm: -20.,
+            right_offset_mm: 20.,
+            extra: Vec::new(),
+            black_sweep_rotations: 0.,
+            black_fraction: DEFAULT_BLACK_FRACTION,
+        };
+    }
+}
+
+impl LineSensingConfig {
+    /// Every sensor's offset: left, right, then the extras in order.
+    pub fn offsets(&self) -> Vec<f32> {
+        let mut offsets = vec![self.left_offset_mm, self.right_offset_mm];
+        offsets.extend(self.extra.iter().map(|sensor| sensor.offset_mm));
+        return offsets;
+    }
+}
+
+/// One downward sensor's reading in a tick, alongside what it read on the surface and the line.
+pub struct LineSample {
+    pub offset_mm: f32,
+    pub reading: RGB,
+    pub surface: RGB,
+    /// Reflectivity over the line
+    pub black: f32,
+}
+
+impl LineSample {
+    /// 0 on the calibration surface up to 1 on the line's black, whatever the lighting.
+    pub fn darkness(&self) -> f32 {
+        let white = self.surface.reflectivity();
+        return ((white - self.reading.reflectivity()) / (white - self.black).max(1.)).clamp(0., 1.);
+    }
+}
+
+/// Where the line is relative to the sensors, and whether it is there at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinePosition {
+    /// -1 with the line under the leftmost sensor (or beyond) to 1 under the rightmost
+    pub offset: f32,
+    /// How dark the darkest sensor reads: 0 when none sees the line, 1 when one is fully on it
+    pub confidence: f32,
+}
+
+impl LinePosition {
+    pub fn sees_line(&self) -> bool {
+        return self.confidence >= MIN_CONFIDENCE;
+    }
+}
+
+/// Lines up a tick's readings with `offsets` (as from `LineSensingConfig::offsets`) and the
+/// calibration. Extra sensors that are missing this tick are left out.
+pub fn samples(offsets: &[f32], profile: &CalibrationProfile, left: &RGB, right: &RGB, extra: &[Option<RGB>]) -> Vec<LineSample> {
+    let mut samples = Vec::with_capacity(offsets.len());
+    let readings = [Some(left), Some(right)].into_iter().chain(extra.iter().map(|reading| reading.as_ref()));
+    let surfaces = [&profile.left, &profile.right].into_iter().chain(profile.extra.iter());
+    for (index, ((offset_mm, reading), surface)) in offsets.iter().zip(readings).zip(surfaces).enumerate() {
+        if let Some(reading) = reading {
+            samples.push(LineSample {
+                offset_mm: *offset_mm,
+                reading: reading.clone(),
+                surface: surface.clone(),
+                black: profile.black(index),
+            });
+        }
+    }
+    return samples;
+}
+
+/// Places the line across the sensors.
+///
+/// A left/right pair straddles the line, so it is centred while neither sees it and the offset
+/// is how much darker the right sensor reads than the left. With more sensors each one's
+/// offset is weighted by how dark it reads, over the outermost sensor's offset; when none
+/// sees the line the offset is 0 and the confidence says so.
+pub fn estimate(samples: &[LineSample]) -> LinePosition {
+    let confidence = samples.iter().map(|sample| sample.darkness()).fold(0., f32::max);
+    if samples.len() <= 2 {
+        let offset = samples.iter().map(|sample| sample.darkness() * sample.offset_mm.signum()).sum::<f32>();
+        return LinePosition {
+            offset: offset.clamp(-1., 1.),
+            confidence,
+        };
+    }
+
+    let total: f32 = samples.iter().map(|sample| sample.darkness()).sum();
+    let span = samples.iter().map(|sample| sample.offset_mm.abs()).fold(0., f32::max);
+    if confidence < MIN_CONFIDENCE || span == 0. {
+        return LinePosition { offset: 0., confidence };
+    }
+    let centroid = samples.iter().map(|sample| sample.darkness() * sample.offset_mm).sum::<f32>() / total;
+    return LinePosition {
+        offset: (centroid / span).clamp(-1., 1.),
+        confidence,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SURFACE: (i32, i32, i32) = (200, 200, 200);
+    // 0.15 of the surface, the default black
+    const LINE: (i32, i32, i32) = (30, 30, 30);
+    const HALF: (i32, i32, i32) = (115, 115, 115);
+
+    fn profile(extra: usize) -> CalibrationProfile {
+        let mut profile = CalibrationProfile::from((RGB::from(SURFACE), RGB::from(SURFACE)));
+        profile.extra = vec![RGB::from(SURFACE); extra];
+        return profile;
+    }
+
+    fn pair(left: (i32, i32, i32), right: (i32, i32, i32)) -> LinePosition {
+        return estimate(&samples(&[-20., 20.], &profile(0), &RGB::from(left), &RGB::from(right), &[]));
+    }
+
+    // Left, right, then a centre sensor
+    fn array(left: (i32, i32, i32), right: (i32, i32, i32), centre: (i32, i32, i32)) -> LinePosition {
+        let extra = [Some(RGB::from(centre))];
+        return estimate(&samples(&[-20., 20., 0.], &profile(1), &RGB::from(left), &RGB::from(right), &extra));
+    }
+
+    #[test]
+    fn pair_offset_follows_the_darker_sensor() {
+        let left = pair(LINE, SURFACE);
+        assert_eq!(left.offset, -1.);
+        assert_eq!(left.confidence, 1.);
+
+        let right = pair(SURFACE, HALF);
+        assert!((right.offset - 0.5).abs() < 0.01, "{:?}", right);
+    }
+
+    #[test]
+    fn pair_straddling_the_line_is_centred_without_confidence() {
+        let position = pair(SURFACE, SURFACE);
+        assert_eq!(position, LinePosition { offset: 0., confidence: 0. });
+        assert!(!position.sees_line());
+    }
+
+    #[test]
+    fn array_centroid_is_signed_by_side() {
+        let centred = array(SURFACE, SURFACE, LINE);
+        assert_eq!(centred.offset, 0.);
+        assert!(centred.sees_line());
+
+        // Dark centre, half dark right: a third of the way out to the right
+        let right = array(SURFACE, HALF, LINE);
+        assert!((right.offset - 1. / 3.).abs() < 0.01, "{:?}", right);
+
+        let left = array(HALF, SURFACE, LINE);
+        assert!((left.offset + 1. / 3.).abs() < 0.01, "{:?}", left);
+
+        assert_eq!(array(LINE, SURFACE, SURFACE).offset, -1.);
+    }
+
+    #[test]
+    fn array_without_the_line_has_no_offset() {
+        let position = array(SURFACE, SURFACE, SURFACE);
+        assert_eq!(position, LinePosition { offset: 0., confidence: 0. });
+        assert!(!position.sees_line());
+    }
+
+    #[test]
+    fn missing_extra_sensor_drops_out() {
+        let position = estimate(&samples(&[-20., 20., 0.], &profile(1), &RGB::from(SURFACE), &RGB::from(LINE), &[None]));
+        assert_eq!(position.offset, 1.);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use crate::mission::Phase;
+
+// Rotate once the current file reaches this size, keeping this many old files
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+const KEPT_FILES: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    // The EV3 console can't do colours, so each level gets a glyph instead
+    fn glyph(&self) -> &'static str {
+        return match self {
+            Level::Error => "(x)",
+            Level::Warn => "(!)",
+            Level::Info => "(?)",
+            Level::Debug => "(>)",
+        };
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!("Unknown log level {:?}", s)),
+        };
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{:?}", self);
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Every run starts in a fresh file so the last run is always icarus.log.1
+        if path.exists() {
+            Self::shift(&path)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        return Ok(Self { path, file, written: 0 });
+    }
+
+    // icarus.log -> icarus.log.1 -> ... -> icarus.log.KEPT_FILES (dropped)
+    fn shift(path: &PathBuf) -> io::Result<()> {
+        let numbered = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+        for n in (1..KEPT_FILES).rev() {
+            if numbered(n).exists() {
+                fs::rename(numbered(n), numbered(n + 1))?;
+            }
+        }
+        return fs::rename(path, numbered(1));
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.written + line.len() as u64 > MAX_FILE_BYTES {
+            Self::shift(&self.path)?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.written = 0;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        return Ok(());
+    }
+}
+
+struct Logger {
+    level: Level,
+    started: Instant,
+    file: Option<LogFile>,
+}
+
+static LOGGER: OnceLock<Mutex<Logger>> = OnceLock::new();
+
+fn logger() -> &'static Mutex<Logger> {
+    return LOGGER.get_or_init(|| {
+        Mutex::new(Logger {
+            level: Level::Info,
+            started: Instant::now(),
+            file: None,
+        })
+    });
+}
+
+/// Sets the level and, optionally, the file that log lines are also written to.
+/// Until this is called everything at `Info` and above goes to the console only.
+pub fn init(level: Level, file: Option<PathBuf>) {
+    let mut logger = logger().lock().unwrap_or_else(|e| e.into_inner());
+    logger.level = level;
+    logger.file = match file.map(LogFile::open) {
+        Some(Ok(file)) => Some(file),
+        Some(Err(e)) => {
+            println!("(!) [ICARUS] » Could not open log file, logging to console only: {}", e);
+            None
+        }
+        None => None,
+    };
+}
+
+pub fn log(level: Level, message: &str) {
+    // Poisoning only means another thread panicked mid-log, which is exactly when we want to keep logging
+    let mut logger = logger().lock().unwrap_or_else(|e| e.into_inner());
+    logger.write(level, message);
+}
+
+/// Logs without waiting on the logger, for use from the panic hook where the
+/// panicking thread may already be holding it.
+pub fn log_from_panic(message: &str) {
+    log_without_waiting(Level::Error, message);
+}
+
+/// Marks the start of `phase`, so the log can be split up by phase afterwards. Phases
+/// also change from the panic hook, so this doesn't wait on the logger either.
+pub fn log_phase(phase: Phase) {
+    log_without_waiting(Level::Info, &format!("Entered {}", phase));
+}
+
+fn log_without_waiting(level: Level, message: &str) {
+    match logger().try_lock() {
+        Ok(mut logger) => logger.write(level, message),
+        Err(_) => {
+            let _ = writeln!(io::stderr(), "{} [{}] » {}", level.glyph(), Phase::current(), message);
+        }
+    }
+}
+
+impl Logger {
+    fn write(&mut self, level: Level, message: &str) {
+        if level > self.level {
+            return;
+        }
+
+        let elapsed = self.started.elapsed();
+        let line = format!(
+            "[{:>5}.{:03}] {} [{}] » {}",
+            elapsed.as_secs(),
+            elapsed.subsec_millis(),
+            level.glyph(),
+            Phase::current(),
+            message
+        );
+        // println! would panic if the SSH session has gone away
+        let _ = writeln!(io::stdout(), "{}", line);
+        if let Some(file) = &mut self.file {
+            if let Err(e) = file.write_line(&line) {
+                let _ = writeln!(io::stdout(), "(!) [ICARUS] » Log file write failed, logging to console only: {}", e);
+                self.file = None;
+            }
+        }
+    }
+}
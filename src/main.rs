@@ -1,13 +1,59 @@
+// Explicit returns are the house style
+#![allow(clippy::needless_return)]
+
 pub mod line_follow;
+pub mod battery;
+pub mod buttons;
 pub mod chemical_spill;
+pub mod cli;
+pub mod colour;
+pub mod config;
+pub mod discovery;
+pub mod drive_monitor;
+pub mod error;
+pub mod indicator;
+pub mod line_sensing;
+pub mod logging;
+pub mod menu;
+pub mod mission;
+pub mod motion;
+pub mod remote;
+pub mod replay;
+pub mod report;
+pub mod screen;
+pub mod selftest;
+pub mod sensors;
+pub mod shutdown;
+pub mod sim;
+pub mod telemetry;
+pub mod trace;
+pub mod training;
 
 extern crate ev3dev_lang_rust;
 
 use ev3dev_lang_rust::motors::{LargeMotor, MediumMotor};
 use ev3dev_lang_rust::{motors::MotorPort, sensors::ColorSensor};
+use std::{path::PathBuf, process};
+
 use ev3dev_lang_rust::Ev3Result;
 use ev3dev_lang_rust::sensors::{SensorPort, UltrasonicSensor};
-use line_follow::{LineFollowParameters, CalibrationProfile};
+use cli::{Cli, Command};
+use colour::ColourConfig;
+use config::Config;
+use indicator::{Cue, Ev3Indicator};
+use line_follow::{FollowMode, LineFollowParameters, CalibrationProfile};
+use line_sensing::LineSensingConfig;
+use battery::Battery;
+use buttons::Buttons;
+use logging::Level;
+use menu::Menu;
+use mission::Phase;
+use error::{IcarusError, IcarusResult};
+use remote::Remote;
+use sensors::{SensorConfig, SensorLink, SensorLinks};
+use shutdown::ShutdownGuard;
+use telemetry::TelemetryPublisher;
+use trace::TraceRecorder;
 
 pub struct Icarus;
 
@@ -15,20 +61,29 @@ pub struct Icarus;
 // been the most devestating thing of all time for me :((
 
 impl Icarus {
-    fn info(message: String) {
-        println!("{} [{}] » {}", "(?)", "ICARUS", message);
+    pub fn error(message: String) {
+        logging::log(Level::Error, &message);
+        indicator::cue(Cue::Error);
     }
-    fn warn(message: String) {
-        println!("{} [{}] » {}", "(!)", "ICARUS", message);
+    pub fn info(message: String) {
+        logging::log(Level::Info, &message);
     }
-    fn debug(message: String) {
-        println!("{} [{}] » {}", "(>)", "ICARUS", message);
+    pub fn warn(message: String) {
+        logging::log(Level::Warn, &message);
+    }
+    pub fn debug(message: String) {
+        logging::log(Level::Debug, &message);
     }
 }
 
 pub struct LineFollowRobot {
     pub left_light: ColorSensor,
     pub right_light: ColorSensor,
+    /// Downward colour sensors beyond the left/right pair, placed by `line_sensing`
+    pub extra_lights: Vec<ColorSensor>,
+    pub line_sensing: LineSensingConfig,
+    /// Colours the classifier knows, for calibration to build it from
+    pub colour: ColourConfig,
     pub ultrasonic: UltrasonicSensor,
     pub left_motor: LargeMotor,
     pub right_motor: LargeMotor,
@@ -36,38 +91,258 @@ pub struct LineFollowRobot {
     pub claw_horiz: MediumMotor,
     pub calibration: Option<CalibrationProfile>,
     pub parameters: LineFollowParameters,
+    pub follow_mode: FollowMode,
+    pub trace: Option<TraceRecorder>,
+    pub trace_dir: Option<PathBuf>,
+    pub telemetry: Option<TelemetryPublisher>,
+    pub remote: Option<Remote>,
+    pub buttons: Option<Buttons>,
+    pub battery: Option<Battery>,
+    pub sensors: SensorLinks,
+    /// Set when a pause asks for the run to restart from a checkpoint
+    pub resume_from: Option<Phase>,
 }
 
 impl LineFollowRobot {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(left_light: SensorPort, right_light: SensorPort, ultrasonic: SensorPort, left_motor: MotorPort, right_motor: MotorPort, claw_vert: MotorPort, claw_horiz: MotorPort, params: LineFollowParameters) -> Ev3Result<Self> {
         return Ok(Self { 
             left_light: ColorSensor::get(left_light)?, 
             right_light: ColorSensor::get(right_light)?, 
+            extra_lights: Vec::new(),
+            line_sensing: LineSensingConfig::default(),
+            colour: ColourConfig::default(),
             ultrasonic: UltrasonicSensor::get(ultrasonic)?,
             left_motor: LargeMotor::get(left_motor)?, 
             right_motor: LargeMotor::get(right_motor)?,
             claw_vert: LargeMotor::get(claw_vert)?,
             claw_horiz: MediumMotor::get(claw_horiz)?,
             calibration: None, 
-            parameters: params 
+            parameters: params,
+            follow_mode: FollowMode::Both,
+            trace: None,
+            trace_dir: None,
+            telemetry: None,
+            remote: None,
+            buttons: None,
+            battery: None,
+            sensors: SensorLinks::new(left_light, right_light, ultrasonic, SensorConfig::default()),
+            resume_from: None,
         });
     }
 }
 
-fn main() -> Ev3Result<()> {
-
-    let mut robot = LineFollowRobot::new(
-        SensorPort::In1, 
-        SensorPort::In2,
-        SensorPort::In3,
-        MotorPort::OutA,
-        MotorPort::OutB,
-        MotorPort::OutC,
-        MotorPort::OutD,
-        LineFollowParameters::new(3., 50, 100, 1.7)
-    )?; 
-    robot.calibrate()?;
-    robot.line_follow()?;
+impl LineFollowRobot {
+    /// Opens every device on the ports named in the config, saying which one is missing if any.
+    pub fn from_config(config: &Config) -> IcarusResult<Self> {
+        let ports = &config.ports;
+        let sensor = |name| config::sensor_port(name).map_err(IcarusError::ConfigInvalid);
+        let mut sensors = SensorLinks::new(
+            sensor(&ports.left_light)?,
+            sensor(&ports.right_light)?,
+            sensor(&ports.ultrasonic)?,
+            config.sensors.clone(),
+        );
+        let mut extra_lights = Vec::new();
+        for extra in &config.line_sensors.extra {
+            let port = sensor(&extra.port)?;
+            extra_lights.push(ColorSensor::get(port).map_err(|e| IcarusError::opening("extra colour sensor", &extra.port, e))?);
+            sensors.extra.push(SensorLink::new(port));
+        }
+        let motor = |name| config::motor_port(name).map_err(IcarusError::ConfigInvalid);
+        return Ok(Self {
+            left_light: ColorSensor::get(sensor(&ports.left_light)?).map_err(|e| IcarusError::opening("left colour sensor", &ports.left_light, e))?,
+            right_light: ColorSensor::get(sensor(&ports.right_light)?).map_err(|e| IcarusError::opening("right colour sensor", &ports.right_light, e))?,
+            extra_lights,
+            line_sensing: config.line_sensors.clone(),
+            colour: config.colour.clone(),
+            ultrasonic: UltrasonicSensor::get(sensor(&ports.ultrasonic)?).map_err(|e| IcarusError::opening("ultrasonic", &ports.ultrasonic, e))?,
+            left_motor: LargeMotor::get(motor(&ports.left_motor)?).map_err(|e| IcarusError::opening("left motor", &ports.left_motor, e))?,
+            right_motor: LargeMotor::get(motor(&ports.right_motor)?).map_err(|e| IcarusError::opening("right motor", &ports.right_motor, e))?,
+            claw_vert: LargeMotor::get(motor(&ports.claw_vert)?).map_err(|e| IcarusError::opening("claw lift", &ports.claw_vert, e))?,
+            claw_horiz: MediumMotor::get(motor(&ports.claw_horiz)?).map_err(|e| IcarusError::opening("claw", &ports.claw_horiz, e))?,
+            calibration: None,
+            parameters: config.parameters.clone(),
+            follow_mode: config.follow_mode,
+            trace: None,
+            trace_dir: None,
+            telemetry: None,
+            remote: None,
+            buttons: None,
+            battery: None,
+            sensors,
+            resume_from: None,
+        });
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        logging::log(Level::Error, &e.to_string());
+        Phase::Shutdown.enter();
+        // After the shutdown cue, so the LEDs are left red rather than off
+        indicator::cue(Cue::Error);
+        // Bad config is a usage error, like a bad argument
+        process::exit(if let IcarusError::ConfigInvalid(_) = e { 2 } else { 1 });
+    }
+}
+
+fn run() -> IcarusResult<()> {
+    let cli = Cli::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, cli::USAGE);
+        process::exit(2);
+    });
+    let mut config = Config::load(cli.config.as_deref(), &cli.overrides).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    let level = cli.log_level.unwrap_or(config.log_level().unwrap_or(Level::Info));
+    // Only runs on the brick are worth keeping a log file for
+    logging::init(level, if cli.command.on_robot() { config.log_file.clone() } else { None });
+
+    match &cli.command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Command::Replay(trace) => match replay::replay(trace) {
+            Ok(0) => process::exit(0),
+            Ok(_) => process::exit(1),
+            Err(e) => {
+                Icarus::error(e);
+                process::exit(2);
+            }
+        },
+        Command::Report(trace, log) => match report::report(trace, log.as_deref()) {
+            Ok(summary) => {
+                print!("{}", summary);
+                return Ok(());
+            }
+            Err(e) => {
+                Icarus::error(e);
+                process::exit(2);
+            }
+        },
+        // Checks each configured port on its own rather than failing on the first missing device
+        Command::Selftest => process::exit(if selftest::selftest(&config) { 0 } else { 1 }),
+        Command::Train(samples, out) => match training::train_from_file(samples, out, &config.colour) {
+            Ok(summary) => {
+                print!("{}", summary);
+                return Ok(());
+            }
+            Err(e) => {
+                Icarus::error(e);
+                process::exit(2);
+            }
+        },
+        Command::Sim { seconds, radius } => {
+            process::exit(if sim::simulate(&config.parameters, *seconds, *radius) { 0 } else { 1 });
+        }
+        Command::Discover => {
+            let path = cli.config.clone().unwrap_or(config::DEFAULT_CONFIG_PATH.into());
+            if let Err(e) = discovery::discover(&mut config, &path) {
+                Icarus::error(e);
+                process::exit(1);
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    match Ev3Indicator::new(config.sound) {
+        Ok(ev3) => {
+            indicator::install(Box::new(ev3));
+            indicator::cue(Cue::Phase(Phase::current()));
+        }
+        Err(e) => Icarus::debug(format!("No status LEDs: {:?}", e)),
+    }
+
+    if config.auto_discover {
+        let found = discovery::attached_devices()?;
+        if let Err(e) = discovery::apply_discovered(&mut config, &found) {
+            Icarus::warn(format!("Auto discovery failed, using configured ports: {}", e));
+        }
+    }
+
+    let mut robot = LineFollowRobot::from_config(&config)?;
+    let _shutdown = ShutdownGuard::install(&robot);
+    robot.trace_dir = config.trace_dir.clone();
+    if let Some(target) = &config.telemetry {
+        match TelemetryPublisher::new(target) {
+            Ok(telemetry) => robot.telemetry = Some(telemetry),
+            Err(e) => Icarus::warn(format!("Telemetry to {} disabled: {}", target, e)),
+        }
+    }
+
+    match Battery::new(config.battery.clone()) {
+        Ok(battery) => robot.battery = Some(battery),
+        Err(e) => Icarus::warn(format!("Battery voltage unavailable: {:?}", e)),
+    }
+    match Buttons::open(&config.buttons) {
+        Ok(buttons) => robot.buttons = Some(buttons),
+        Err(e) => Icarus::debug(format!("No brick buttons on {}: {}", config.buttons.display(), e)),
+    }
+
+    match cli.command {
+        Command::Run => {
+            if let Some(address) = &config.remote {
+                match Remote::listen(address) {
+                    Ok(remote) => robot.remote = Some(remote),
+                    Err(e) => Icarus::warn(format!("Remote control on {} disabled: {}", address, e)),
+                }
+            }
+            robot.calibrate()?;
+            // With remote control the robot waits for a start from either the buttons or the remote,
+            // and goes back to waiting after each run until the start is called off
+            loop {
+                let start = if config.wait_for_start || robot.remote.is_some() {
+                    robot.wait_for_start(config.start_from)?
+                } else {
+                    Some(config.start_from)
+                };
+                let Some(phase) = start else { break };
+                robot.run_course(phase)?;
+                if robot.remote.is_none() {
+                    break;
+                }
+                Icarus::info("Run over, waiting for the next start".to_string());
+            }
+        }
+        Command::Calibrate => robot.calibrate()?,
+        Command::Follow => {
+            robot.calibrate()?;
+            robot.run_course(Phase::LineFollow)?;
+        }
+        Command::Spill => {
+            robot.chemical_spill()?;
+            robot.pickup_can()?;
+        }
+        Command::WaterTower => robot.avoid_water_tower()?,
+        Command::Claw(claw_move) => robot.exercise_claw(claw_move)?,
+        Command::Menu if robot.buttons.is_none() => {
+            return Err(IcarusError::ButtonsMissing {
+                needed_by: "The menu",
+                device: config.buttons.display().to_string(),
+            });
+        }
+        Command::Menu => robot.run_menu(&mut Menu::new(&config.menu), &mut config)?,
+        Command::Collect(_) if robot.buttons.is_none() => {
+            return Err(IcarusError::ButtonsMissing {
+                needed_by: "Collecting samples",
+                device: config.buttons.display().to_string(),
+            });
+        }
+        Command::Collect(path) => robot.collect_samples(&path, &mut Menu::new(&config.menu))?,
+        Command::Selftest
+        | Command::Discover
+        | Command::Replay(_)
+        | Command::Report(..)
+        | Command::Sim { .. }
+        | Command::Train(..)
+        | Command::Help => {}
+    }
 
+    Phase::Shutdown.enter();
     Ok(())
 }
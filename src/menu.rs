@@ -0,0 +1,230 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buttons::{Button, Buttons},
+    config::Config,
+    error::IcarusResult,
+    mission::{Phase, CHECKPOINTS},
+    screen::{Screen, ScreenConfig, LINE_HEIGHT},
+    selftest, Icarus, LineFollowRobot,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MenuConfig {
+    pub screen: ScreenConfig,
+    /// Config files offered under "Profile"
+    pub profiles_dir: PathBuf,
+}
+
+impl Default for MenuConfig {
+    fn default() -> Self {
+        return Self {
+            screen: ScreenConfig::default(),
+            profiles_dir: "/home/robot/profiles".into(),
+        };
+    }
+}
+
+pub enum MenuChoice {
+    Calibrate,
+    Run,
+    Resume(Phase),
+    Selftest,
+    Profile(PathBuf),
+}
+
+const TITLE_SCALE: usize = 2;
+const ITEMS_TOP: usize = 8 * TITLE_SCALE + 6;
+const ROW_HEIGHT: usize = LINE_HEIGHT + 2;
+
+/// Room for the title, one item and the status line.
+pub const MIN_SCREEN_HEIGHT: usize = ITEMS_TOP + ROW_HEIGHT + LINE_HEIGHT;
+
+pub struct Menu {
+    screen: Screen,
+    profiles_dir: PathBuf,
+}
+
+impl Menu {
+    pub fn new(config: &MenuConfig) -> Self {
+        return Self {
+            screen: Screen::new(config.screen.clone()),
+            profiles_dir: config.profiles_dir.clone(),
+        };
+    }
+
+    /// Shows the main menu until something is chosen. `None` when backed out of, or when
+    /// the buttons have nothing more to say.
+    pub fn choose(&mut self, buttons: &mut Buttons, status: &str) -> io::Result<Option<MenuChoice>> {
+        let items = ["Calibrate", "Run", "Resume from", "Self test", "Profile"].map(String::from);
+        loop {
+            let choice = match self.pick(buttons, "ICARUS", status, &items)? {
+                Some(0) => MenuChoice::Calibrate,
+                Some(1) => MenuChoice::Run,
+                Some(2) => {
+                    let phases = CHECKPOINTS.map(|phase| phase.to_string());
+                    match self.pick(buttons, "RESUME", "", &phases)? {
+                        Some(i) => MenuChoice::Resume(CHECKPOINTS[i]),
+                        None => continue,
+                    }
+                }
+                Some(3) => MenuChoice::Selftest,
+                Some(_) => {
+                    let profiles = profiles(&self.profiles_dir);
+                    if profiles.is_empty() {
+                        self.message("PROFILE", &["No profiles in", &self.profiles_dir.display().to_string()])?;
+                        continue;
+                    }
+                    let names = profiles.iter().map(|path| profile_name(path)).collect::<Vec<_>>();
+                    match self.pick(buttons, "PROFILE", "", &names)? {
+                        Some(i) => MenuChoice::Profile(profiles[i].clone()),
+                        None => continue,
+                    }
+                }
+                None => return Ok(None),
+            };
+            return Ok(Some(choice));
+        }
+    }
+
+    /// Puts a few lines up under a title, e.g. while an action runs.
+    pub fn message(&mut self, title: &str, lines: &[&str]) -> io::Result<()> {
+        self.screen.clear();
+        self.screen.text(2, 2, title, TITLE_SCALE, false);
+        for (i, line) in lines.iter().enumerate() {
+            self.screen.text(2, ITEMS_TOP + i * ROW_HEIGHT, line, 1, false);
+        }
+        return self.screen.present();
+    }
+
+    /// Lets the user move through `items` with up and down and pick one with enter.
+    fn pick(&mut self, buttons: &mut Buttons, title: &str, status: &str, items: &[String]) -> io::Result<Option<usize>> {
+        let mut selected = 0;
+        loop {
+            self.draw(title, status, items, selected)?;
+            match buttons.next_press()? {
+                Some(Button::Up) => selected = (selected + items.len() - 1) % items.len(),
+                Some(Button::Down) => selected = (selected + 1) % items.len(),
+                Some(Button::Enter) | Some(Button::Right) => return Ok(Some(selected)),
+                Some(Button::Back) | Some(Button::Left) | None => return Ok(None),
+            }
+        }
+    }
+
+    fn draw(&mut self, title: &str, status: &str, items: &[String], selected: usize) -> io::Result<()> {
+        let width = self.screen.width();
+        // Leave the bottom row for the status line
+        let visible = (self.screen.height().saturating_sub(ITEMS_TOP) / ROW_HEIGHT).saturating_sub(1).max(1);
+        let first = selected.saturating_sub(visible - 1);
+
+        self.screen.clear();
+        self.screen.text(2, 2, title, TITLE_SCALE, false);
+        for (row, (i, item)) in items.iter().enumerate().skip(first).take(visible).enumerate() {
+            let y = ITEMS_TOP + row * ROW_HEIGHT;
+            if i == selected {
+                self.screen.fill(0, y - 1, width, ROW_HEIGHT - 1, true);
+            }
+            self.screen.text(4, y, item, 1, i == selected);
+        }
+        self.screen.text(2, self.screen.height().saturating_sub(LINE_HEIGHT), status, 1, false);
+        return self.screen.present();
+    }
+}
+
+fn profiles(dir: &Path) -> Vec<PathBuf> {
+    let mut profiles = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    profiles.sort();
+    return profiles;
+}
+
+fn profile_name(path: &Path) -> String {
+    return path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+}
+
+impl LineFollowRobot {
+    /// Runs whatever is picked on the brick until the menu is backed out of. Failures
+    /// are shown and logged, then it's back to the menu.
+    pub fn run_menu(&mut self, menu: &mut Menu, config: &mut Config) -> IcarusResult<()> {
+        let mut profile = "default".to_string();
+        loop {
+            let calibrated = if self.calibration.is_some() { "calibrated" } else { "not calibrated" };
+            let status = format!("{} - {}", profile, calibrated);
+            let chosen = match &mut self.buttons {
+                Some(buttons) => menu.choose(buttons, &status),
+                None => Ok(None),
+            };
+            let choice = match chosen {
+                Ok(Some(choice)) => choice,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    Icarus::error(format!("Menu input failed: {}", e));
+                    return Ok(());
+                }
+            };
+
+            let shown = match &choice {
+                MenuChoice::Calibrate => menu.message("CALIBRATE", &["Place on the mat", "Starting in 3s"]),
+                MenuChoice::Run => menu.message("RUN", &["Calibrating, then", "following the line"]),
+                MenuChoice::Resume(phase) => menu.message("RESUME", &[&phase.to_string()]),
+                MenuChoice::Selftest => menu.message("SELF TEST", &["Checking devices"]),
+                MenuChoice::Profile(_) => Ok(()),
+            };
+            if let Err(e) = shown {
+                Icarus::warn(format!("Could not draw on the screen: {}", e));
+            }
+
+            let outcome = match choice {
+                MenuChoice::Calibrate => self.calibrate().map(|_| "Calibrated".to_string()),
+                MenuChoice::Run => self
+                    .calibrate()
+                    .and_then(|_| self.run_course(Phase::LineFollow))
+                    .map(|_| "Finished".to_string()),
+                MenuChoice::Resume(phase) => self.run_course(phase).map(|_| "Finished".to_string()),
+                MenuChoice::Selftest => Ok(if selftest::selftest(config) { "All devices OK" } else { "Some devices failed" }.to_string()),
+                MenuChoice::Profile(path) => match Config::load(Some(&path), &[]) {
+                    Ok(loaded) => {
+                        if serde_json::to_value(&loaded.ports).ok() != serde_json::to_value(&config.ports).ok() {
+                            Icarus::warn("Profile ports differ from the running ones, restart to use them".to_string());
+                        }
+                        self.parameters = loaded.parameters.clone();
+                        *config = loaded;
+                        profile = profile_name(&path);
+                        Icarus::info(format!("Loaded profile {}", path.display()));
+                        Ok(format!("Loaded {}", profile))
+                    }
+                    Err(e) => {
+                        Icarus::error(e.to_string());
+                        Ok("Profile invalid".to_string())
+                    }
+                },
+            };
+
+            let (title, detail) = match outcome {
+                Ok(detail) => ("DONE", detail),
+                Err(e) => {
+                    Icarus::error(format!("{}", e));
+                    ("FAILED", e.to_string())
+                }
+            };
+            if let Err(e) = menu.message(title, &[&detail, "", "Any button for menu"]) {
+                Icarus::warn(format!("Could not draw on the screen: {}", e));
+            }
+            if let Some(Ok(None) | Err(_)) = self.buttons.as_mut().map(|buttons| buttons.next_press()) {
+                return Ok(());
+            }
+        }
+    }
+}
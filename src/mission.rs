@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU8, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    buttons::Button,
+    error::IcarusResult,
+    indicator::{self, Cue},
+    logging,
+    remote::{RemoteAction, RunState},
+    Icarus, LineFollowRobot,
+};
+
+/// The part of the course the robot is currently working on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Startup,
+    Calibration,
+    LineFollow,
+    WaterTower,
+    ChemicalSpill,
+    Shutdown,
+}
+
+const PHASES: [Phase; 6] = [
+    Phase::Startup,
+    Phase::Calibration,
+    Phase::LineFollow,
+    Phase::WaterTower,
+    Phase::ChemicalSpill,
+    Phase::Shutdown,
+];
+
+static CURRENT_PHASE: AtomicU8 = AtomicU8::new(0);
+
+impl Phase {
+    pub fn current() -> Phase {
+        return PHASES[CURRENT_PHASE.load(Ordering::Relaxed) as usize];
+    }
+
+    pub fn enter(self) {
+        if self.replace(&CURRENT_PHASE) {
+            logging::log_phase(self);
+            indicator::cue(Cue::Phase(self));
+        }
+    }
+
+    // Stores `self` as the phase in `current`; true if that changed it
+    fn replace(self, current: &AtomicU8) -> bool {
+        return current.swap(self as u8, Ordering::Relaxed) != self as u8;
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.write_str(match self {
+            Phase::Startup => "STARTUP",
+            Phase::Calibration => "CALIBRATION",
+            Phase::LineFollow => "LINE FOLLOW",
+            Phase::WaterTower => "WATER TOWER",
+            Phase::ChemicalSpill => "CHEMICAL SPILL",
+            Phase::Shutdown => "SHUTDOWN",
+        });
+    }
+}
+
+/// Phases a run can be started or picked up from. Resuming from the water tower
+/// drives the detour, then carries on line following after it.
+pub const CHECKPOINTS: [Phase; 3] = [Phase::LineFollow, Phase::WaterTower, Phase::ChemicalSpill];
+
+/// What to do once a pause is over.
+#[derive(Debug, PartialEq)]
+pub enum Resume {
+    Continue,
+    From(Phase),
+    Abort,
+}
+
+// How often waiting loops look at the buttons and remote
+const WAIT_POLL: Duration = Duration::from_millis(50);
+
+impl LineFollowRobot {
+    /// Runs the course from `from` to the end, restarting at whichever checkpoint a pause
+    /// asks for. The calibration is kept throughout.
+    pub fn run_course(&mut self, from: Phase) -> IcarusResult<()> {
+        let mut phase = from;
+        loop {
+            self.resume_from = None;
+            match phase {
+                Phase::WaterTower => {
+                    let detour = self.avoid_water_tower();
+                    self.recover(detour)?;
+                    self.line_follow()?;
+                }
+                Phase::ChemicalSpill => {
+                    self.chemical_spill()?;
+                    self.pickup_can()?;
+                }
+                _ => self.line_follow()?,
+            }
+            match self.resume_from.take() {
+                Some(next) => {
+                    Icarus::info(format!("Restarting from {}", next));
+                    phase = next;
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Holds still until told to go by the enter button or a remote `start`/`resume`.
+    /// Up and down pick the checkpoint to start from. `None` if the start was called off.
+    pub fn wait_for_start(&mut self, phase: Phase) -> IcarusResult<Option<Phase>> {
+        if self.buttons.is_none() && self.remote.is_none() {
+            Icarus::warn("No buttons or remote to start with, starting straight away".to_string());
+            return Ok(Some(phase));
+        }
+        Icarus::info(format!("Waiting for start from {}: enter to go, up/down to change", phase));
+        return match self.hold(phase, RunState::Idle)? {
+            Resume::Continue => Ok(Some(phase)),
+            Resume::From(phase) => Ok(Some(phase)),
+            Resume::Abort => Ok(None),
+        };
+    }
+
+    /// Called every tick while running; pauses if enter has been pressed since.
+    pub fn poll_pause(&mut self) -> IcarusResult<Resume> {
+        if self.poll_buttons() == Some(Button::Enter) {
+            return self.pause();
+        }
+        return Ok(Resume::Continue);
+    }
+
+    /// Stops the drive and waits. Enter (or a remote `resume`) carries on where it left
+    /// off, unless up/down picked a checkpoint to restart from; back (or `stop`) gives up.
+    pub fn pause(&mut self) -> IcarusResult<Resume> {
+        self.stop_drive()?;
+        Icarus::info("Paused: enter to continue, up/down to pick a checkpoint, back to abort".to_string());
+        let resume = self.hold(Phase::current(), RunState::Paused)?;
+        Icarus::info(format!("Unpaused: {:?}", resume));
+        return Ok(resume);
+    }
+
+    // Waits on the buttons and remote. `Continue` means enter was pressed without
+    // moving off `current`.
+    fn hold(&mut self, current: Phase, state: RunState) -> IcarusResult<Resume> {
+        let mut selected = CHECKPOINTS.iter().position(|&phase| phase == current);
+        loop {
+            match self.service_remote(state)? {
+                RemoteAction::Start(phase) | RemoteAction::Resume(Some(phase)) => return Ok(Resume::From(phase)),
+                RemoteAction::Resume(None) => return Ok(Resume::Continue),
+                RemoteAction::Stop => return Ok(Resume::Abort),
+                RemoteAction::Pause | RemoteAction::Continue => {}
+            }
+
+            let step = match self.poll_buttons() {
+                Some(Button::Enter) => {
+                    return Ok(match selected.map(|i| CHECKPOINTS[i]) {
+                        Some(phase) if phase != current => Resume::From(phase),
+                        _ => Resume::Continue,
+                    });
+                }
+                Some(Button::Back) => return Ok(Resume::Abort),
+                Some(Button::Up) => CHECKPOINTS.len() - 1,
+                Some(Button::Down) => 1,
+                _ => 0,
+            };
+            if step != 0 {
+                let next = selected.map_or(0, |i| (i + step) % CHECKPOINTS.len());
+                selected = Some(next);
+                Icarus::info(format!("Checkpoint: {}", CHECKPOINTS[next]));
+            }
+            thread::sleep(WAIT_POLL);
+        }
+    }
+
+    // A button device that fails once is given up on rather than ending the run
+    fn poll_buttons(&mut self) -> Option<Button> {
+        let pressed = self.buttons.as_mut()?.poll_press();
+        return pressed.unwrap_or_else(|e| {
+            Icarus::warn(format!("Brick buttons failed, ignoring them: {}", e));
+            self.buttons = None;
+            None
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_change_of_phase_counts_as_entering() {
+        let current = AtomicU8::new(Phase::Startup as u8);
+        let entered: Vec<bool> = [Phase::Calibration, Phase::LineFollow, Phase::LineFollow, Phase::WaterTower, Phase::LineFollow]
+            .into_iter()
+            .map(|phase| phase.replace(&current))
+            .collect();
+        assert_eq!(entered, [true, true, false, true, true]);
+        assert_eq!(current.load(Ordering::Relaxed), Phase::LineFollow as u8);
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::{Duration, Instant};
+
+use ev3dev_lang_rust::{
+    motors::{LargeMotor, MediumMotor},
+    Device, Ev3Result,
+};
+
+use crate::{
+    error::{Context, IcarusError, IcarusResult},
+    mission::Phase,
+    LineFollowRobot,
+};
+
+// Allowance on top of the expected move duration before we call it stuck
+const DEADLINE_FACTOR: f32 = 2.;
+const DEADLINE_SLACK: Duration = Duration::from_millis(500);
+
+/// Time `motor` should be given to travel `counts` at `speed` counts/s. A speed of 0 would never get there.
+pub fn deadline_for(motor: &impl Device, counts: i32, speed: i32) -> IcarusResult<Duration> {
+    if speed == 0 {
+        return Err(IcarusError::Stationary {
+            motor: motor.get_address().unwrap_or_else(|_| "unknown".to_string()),
+            phase: Phase::current(),
+        });
+    }
+    let expected = counts.abs() as f32 / speed.abs() as f32;
+    return Ok(Duration::from_secs_f32(expected * DEADLINE_FACTOR) + DEADLINE_SLACK);
+}
+
+/// The subset of tacho motor behaviour needed to wait on a move.
+pub trait BlockingMotor: Device + Sized {
+    /// What errors call the motor, alongside its port
+    const NAME: &'static str;
+
+    fn wait_until_stopped(&self, timeout: Option<Duration>) -> bool;
+    fn halt(&self) -> Ev3Result<()>;
+    fn position(&self) -> Ev3Result<i32>;
+
+    /// Turns the motor by `rotations` at `speed` counts/s and waits for it to get there.
+    fn run_rotations(&self, rotations: f32, speed: i32) -> IcarusResult<()>;
+
+    /// Blocks until the motor stops running, or stops it and errors once `deadline` has passed.
+    fn wait_or_timeout(&self, deadline: Duration) -> IcarusResult<()> {
+        if self.wait_until_stopped(Some(deadline)) {
+            return Ok(());
+        }
+        self.halt().context(self, Self::NAME, "stop")?;
+        return Err(IcarusError::Timeout {
+            motor: self.get_address().unwrap_or_else(|_| "unknown".to_string()),
+            deadline,
+            phase: Phase::current(),
+        });
+    }
+}
+
+macro_rules! blocking_motor {
+    ($motor:ty, $name:literal) => {
+        impl BlockingMotor for $motor {
+            const NAME: &'static str = $name;
+
+            #[cfg(target_os = "linux")]
+            fn wait_until_stopped(&self, timeout: Option<Duration>) -> bool {
+                return self.wait_until_not_moving(timeout);
+            }
+            #[cfg(not(target_os = "linux"))]
+            fn wait_until_stopped(&self, _timeout: Option<Duration>) -> bool {
+                return true;
+            }
+            fn halt(&self) -> Ev3Result<()> {
+                return self.stop();
+            }
+            fn position(&self) -> Ev3Result<i32> {
+                return self.get_position();
+            }
+            fn run_rotations(&self, rotations: f32, speed: i32) -> IcarusResult<()> {
+                let counts = (self.get_count_per_rot().context(self, $name, "read counts per rotation")? as f32 * rotations) as i32;
+                let deadline = deadline_for(self, counts, speed)?;
+                self.set_speed_sp(speed).context(self, $name, "set speed")?;
+                self.run_to_rel_pos(Some(counts)).context(self, $name, "run")?;
+                return self.wait_or_timeout(deadline);
+            }
+        }
+    };
+}
+
+blocking_motor!(LargeMotor, "large motor");
+blocking_motor!(MediumMotor, "medium motor");
+
+impl LineFollowRobot {
+    /// Turns each drive wheel the given number of rotations at the given speeds and waits for both.
+    pub fn drive_rotations(
+        &self,
+        left_rotations: f32,
+        right_rotations: f32,
+        left_speed: i32,
+        right_speed: i32,
+    ) -> IcarusResult<()> {
+        let left_counts = (self.left_motor.get_count_per_rot().context(&self.left_motor, "left motor", "read counts per rotation")? as f32 * left_rotations) as i32;
+        let right_counts = (self.right_motor.get_count_per_rot().context(&self.right_motor, "right motor", "read counts per rotation")? as f32 * right_rotations) as i32;
+        let deadline = deadline_for(&self.left_motor, left_counts, left_speed)?.max(deadline_for(&self.right_motor, right_counts, right_speed)?);
+        self.left_motor.set_position_sp(left_counts).context(&self.left_motor, "left motor", "set position")?;
+        self.right_motor.set_position_sp(right_counts).context(&self.right_motor, "right motor", "set position")?;
+        self.left_motor.set_speed_sp(left_speed).context(&self.left_motor, "left motor", "set speed")?;
+        self.right_motor.set_speed_sp(right_speed).context(&self.right_motor, "right motor", "set speed")?;
+        self.left_motor.run_to_rel_pos(None).context(&self.left_motor, "left motor", "run")?;
+        self.right_motor.run_to_rel_pos(None).context(&self.right_motor, "right motor", "run")?;
+
+        return self.wait_drive(deadline);
+    }
+
+    /// Runs both drive wheels for `duration` at the given speeds and waits for both.
+    pub fn drive_timed(&self, duration: Duration, left_speed: i32, right_speed: i32) -> IcarusResult<()> {
+        self.left_motor.set_time_sp(duration.as_millis() as i32).context(&self.left_motor, "left motor", "set time")?;
+        self.right_motor.set_time_sp(duration.as_millis() as i32).context(&self.right_motor, "right motor", "set time")?;
+        self.left_motor.set_speed_sp(left_speed).context(&self.left_motor, "left motor", "set speed")?;
+        self.right_motor.set_speed_sp(right_speed).context(&self.right_motor, "right motor", "set speed")?;
+        self.left_motor.run_timed(None).context(&self.left_motor, "left motor", "run")?;
+        self.right_motor.run_timed(None).context(&self.right_motor, "right motor", "run")?;
+
+        return self.wait_drive(duration + DEADLINE_SLACK);
+    }
+
+    // Waits for both drive wheels against one deadline, stopping both as soon as either runs out of time
+    fn wait_drive(&self, allowed: Duration) -> IcarusResult<()> {
+        let deadline = Instant::now() + allowed;
+        let waited = self
+            .right_motor
+            .wait_or_timeout(deadline.saturating_duration_since(Instant::now()))
+            .and_then(|()| self.left_motor.wait_or_timeout(deadline.saturating_duration_since(Instant::now())));
+        if let Err(IcarusError::Timeout { motor, phase, .. }) = waited {
+            self.stop_drive()?;
+            return Err(IcarusError::Timeout {
+                motor,
+                deadline: allowed,
+                phase,
+            });
+        }
+        return waited;
+    }
+
+    /// Stops both drive wheels where they are.
+    pub fn stop_drive(&self) -> IcarusResult<()> {
+        self.left_motor.stop().context(&self.left_motor, "left motor", "stop")?;
+        self.right_motor.stop().context(&self.right_motor, "right motor", "stop")?;
+        return Ok(());
+    }
+}
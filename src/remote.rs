@@ -0,0 +1,207 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    error::{IcarusError, IcarusResult},
+    line_follow::Parameter,
+    mission::{Phase, CHECKPOINTS},
+    trace::TraceEvent,
+    Icarus, LineFollowRobot,
+};
+
+// How long a client waits for the control loop to pick up its command (calibration takes ~4 s)
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One request per line, e.g.
+/// `{"cmd": "set", "field": "kp", "value": 3.5}` or `{"cmd": "start", "phase": "line_follow"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    Status,
+    Set { field: String, value: f64 },
+    Calibrate,
+    Start { phase: Phase },
+    Pause,
+    /// Carries on after a pause, or restarts from `from` if given
+    Resume { from: Option<Phase> },
+    Stop,
+}
+
+struct Request {
+    command: Command,
+    reply: Sender<Value>,
+}
+
+/// What the control loop has to do after servicing remote commands.
+#[derive(Debug, PartialEq)]
+pub enum RemoteAction {
+    Continue,
+    Start(Phase),
+    Pause,
+    Resume(Option<Phase>),
+    Stop,
+}
+
+/// What the control loop is doing while it services the remote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunState {
+    Idle,
+    Running,
+    /// Stopped mid-run, with the line follow state still held
+    Paused,
+}
+
+/// The robot's end of the command server. The server threads only parse and forward
+/// requests; they are answered from the control loop so nothing changes mid-tick.
+pub struct Remote {
+    requests: Receiver<Request>,
+}
+
+impl Remote {
+    /// Listens on `address` (e.g. `0.0.0.0:5006`) for newline-delimited JSON commands.
+    pub fn listen(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let (sender, requests) = mpsc::channel();
+        Icarus::info(format!("Accepting remote commands on {}", address));
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve_client(stream, sender) {
+                        Icarus::debug(format!("Remote client dropped: {}", e));
+                    }
+                });
+            }
+        });
+
+        return Ok(Self { requests });
+    }
+}
+
+fn serve_client(stream: TcpStream, requests: Sender<Request>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Command>(&line) {
+            Ok(command) => {
+                let (reply, response) = mpsc::channel();
+                if requests.send(Request { command, reply }).is_err() {
+                    return Ok(());
+                }
+                response
+                    .recv_timeout(REPLY_TIMEOUT)
+                    .unwrap_or_else(|_| json!({ "ok": false, "error": "robot did not respond" }))
+            }
+            Err(e) => json!({ "ok": false, "error": format!("bad command: {}", e) }),
+        };
+        writeln!(writer, "{}", response)?;
+    }
+    return Ok(());
+}
+
+impl LineFollowRobot {
+    /// Answers any pending remote commands. A phase in progress rules out starting another,
+    /// and calibrating is only allowed between runs, as a paused run keeps its calibration.
+    pub fn service_remote(&mut self, state: RunState) -> IcarusResult<RemoteAction> {
+        let running = state == RunState::Running;
+        let mut action = RemoteAction::Continue;
+        let pending: Vec<Request> = match &self.remote {
+            Some(remote) => remote.requests.try_iter().collect(),
+            None => return Ok(action),
+        };
+
+        for request in pending {
+            Icarus::debug(format!("Remote command: {:?}", request.command));
+            let response = match request.command {
+                Command::Status => json!({
+                    "ok": true,
+                    "phase": Phase::current(),
+                    "running": running,
+                    "paused": state == RunState::Paused,
+                    "calibrated": self.calibration.is_some(),
+                    "kp": self.parameters.kp,
+                    "tick": self.parameters.tick,
+                    "targeted_speed": self.parameters.targeted_speed,
+                    "green_threshold": self.parameters.green_threshold,
+                }),
+                Command::Set { field, value } => match self.set_parameter(&field, value) {
+                    Ok(parameter) => {
+                        Icarus::info(format!("Remote set {} to {}", field, value));
+                        // Kept in the same trace, so a replay carries the controller's state across the change
+                        let value = self.effective_parameters().get(parameter);
+                        if let Some(trace) = &mut self.trace {
+                            trace.note(TraceEvent::Set(parameter, value));
+                        }
+                        json!({ "ok": true })
+                    }
+                    Err(e) => json!({ "ok": false, "error": e }),
+                },
+                Command::Calibrate if state != RunState::Idle => json!({ "ok": false, "error": "stop before calibrating" }),
+                // A failed calibration is the client's to retry, not a reason to stop serving
+                Command::Calibrate => match self.calibrate() {
+                    Ok(()) => json!({ "ok": true }),
+                    Err(e) => {
+                        Icarus::warn(format!("Remote calibration failed: {}", e));
+                        json!({ "ok": false, "error": e.to_string() })
+                    }
+                },
+                Command::Start { .. } if running => json!({ "ok": false, "error": "already running" }),
+                Command::Start { phase } if phase != Phase::ChemicalSpill && self.calibration.is_none() => {
+                    json!({ "ok": false, "error": IcarusError::CalibrationMissing { phase }.to_string() })
+                }
+                Command::Start { phase } if CHECKPOINTS.contains(&phase) => {
+                    action = RemoteAction::Start(phase);
+                    json!({ "ok": true })
+                }
+                Command::Start { phase } => json!({ "ok": false, "error": format!("{} cannot be started", phase) }),
+                Command::Pause if running => {
+                    action = RemoteAction::Pause;
+                    json!({ "ok": true })
+                }
+                Command::Pause => json!({ "ok": false, "error": "not running" }),
+                Command::Resume { .. } if running => json!({ "ok": false, "error": "not paused" }),
+                Command::Resume { from: Some(phase) } if !CHECKPOINTS.contains(&phase) => {
+                    json!({ "ok": false, "error": format!("{} cannot be resumed from", phase) })
+                }
+                Command::Resume { from } => {
+                    action = RemoteAction::Resume(from);
+                    json!({ "ok": true })
+                }
+                Command::Stop => {
+                    action = RemoteAction::Stop;
+                    json!({ "ok": true })
+                }
+            };
+            let _ = request.reply.send(response);
+        }
+
+        return Ok(action);
+    }
+
+    fn set_parameter(&mut self, field: &str, value: f64) -> Result<Parameter, String> {
+        let parameter: Parameter = field.parse()?;
+        let in_range = match parameter {
+            Parameter::Kp => true,
+            Parameter::GreenThreshold => value > 0.,
+            Parameter::TargetedSpeed => value > 0. && value <= 1000.,
+            Parameter::Tick => value >= 1.,
+        };
+        if !in_range {
+            return Err(format!("{} out of range for {}", value, field));
+        }
+        self.parameters.set(parameter, value as f32);
+        return Ok(parameter);
+    }
+}
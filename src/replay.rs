@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use crate::{
+    line_follow::{LineFollowController, Side},
+    line_sensing,
+    trace::{Trace, TraceEvent},
+};
+
+// Recorded speeds went through an f32 -> i32 cast on the robot too, so allow for rounding
+const SPEED_TOLERANCE: i32 = 1;
+
+/// Feeds a recorded trace back through the line follow controller and reports every
+/// tick where the decisions differ from what the robot did. Returns the number of
+/// mismatched ticks.
+pub fn replay(path: &Path) -> Result<usize, String> {
+    let trace = Trace::read(path)?;
+    let mut parameters = trace.parameters.clone();
+    let mut controller = LineFollowController::new();
+    let mut mismatches = 0;
+
+    println!("Replaying {} ticks from {}", trace.ticks.len(), path.display());
+    for (t_ms, recorded) in &trace.ticks {
+        let mut differences = Vec::<String>::new();
+
+        // Remote changes came in before the tick that records them
+        for event in &recorded.events {
+            if let TraceEvent::Set(parameter, value) = event {
+                parameters.set(*parameter, *value);
+            }
+        }
+
+        let water_tower = controller.sees_water_tower(recorded.ultrasonic);
+        if water_tower != recorded.events.contains(&TraceEvent::WaterTower) {
+            differences.push(format!("water tower: replayed {}, recorded {}", water_tower, !water_tower));
+        }
+
+        let edge_sensor = recorded.events.iter().find_map(|e| match e {
+            TraceEvent::Edge(side) => Some(*side),
+            _ => None,
+        });
+        let steering = match edge_sensor {
+            Some(Side::Left) => controller.step_edge(&parameters, &trace.calibration, Side::Left, &recorded.left_raw),
+            Some(Side::Right) => controller.step_edge(&parameters, &trace.calibration, Side::Right, &recorded.right_raw),
+            None if !recorded.extra_raw.is_empty() => {
+                let samples = line_sensing::samples(
+                    &trace.offsets,
+                    &trace.calibration,
+                    &recorded.left_raw,
+                    &recorded.right_raw,
+                    &recorded.extra_raw,
+                );
+                controller.step_array(&parameters, &trace.calibration, &recorded.left_raw, &recorded.right_raw, &samples)
+            }
+            None => controller.step(
+                &parameters,
+                &trace.calibration,
+                &recorded.left_raw,
+                &recorded.right_raw,
+            ),
+        };
+        let recorded_green = recorded.events.iter().find_map(|e| match e {
+            TraceEvent::Green(side) => Some(*side),
+            _ => None,
+        });
+        if steering.green_turn != recorded_green {
+            differences.push(format!(
+                "green turn: replayed {:?}, recorded {:?}",
+                steering.green_turn, recorded_green
+            ));
+        }
+        if (steering.left_speed - recorded.left_speed).abs() > SPEED_TOLERANCE
+            || (steering.right_speed - recorded.right_speed).abs() > SPEED_TOLERANCE
+        {
+            differences.push(format!(
+                "speeds: replayed {}/{}, recorded {}/{}",
+                steering.left_speed, steering.right_speed, recorded.left_speed, recorded.right_speed
+            ));
+        }
+
+        // Slips were detected from motor feedback we don't have offline, so take the robot's word for them
+        if recorded.events.contains(&TraceEvent::Slip) {
+            controller.on_slip();
+        }
+
+        if !differences.is_empty() {
+            mismatches += 1;
+            println!("{:>8} ms: {}", t_ms, differences.join("; "));
+        }
+    }
+
+    println!("{} of {} ticks differ", mismatches, trace.ticks.len());
+    return Ok(mismatches);
+}
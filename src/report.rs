@@ -0,0 +1,284 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use crate::{
+    line_follow::RGB,
+    line_sensing,
+    mission::Phase,
+    trace::{Trace, TraceEvent, TraceTick},
+};
+
+// Reflectivity this close to the calibration surface on both sides of the pair means no line is seen
+const LOST_LINE_BAND: f32 = 10.;
+// Consecutive lineless ticks that count as losing the line rather than crossing a gap
+const LOST_LINE_TICKS: usize = 10;
+
+const SVG_WIDTH: f32 = 1000.;
+const PANEL_HEIGHT: f32 = 200.;
+const MARGIN: f32 = 40.;
+
+// A line colour and one value per tick
+type Series<'a> = (&'a str, Vec<f32>);
+
+/// Summarises a run from its trace and, optionally, its log file. The text summary is
+/// returned and a plot of heading and motor speeds is written next to the trace as SVG.
+pub fn report(trace_path: &Path, log_path: Option<&Path>) -> Result<String, String> {
+    let trace = Trace::read(trace_path)?;
+    let log = match log_path {
+        Some(path) => Some((path, fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?)),
+        None => None,
+    };
+    let ticks = &trace.ticks;
+    let mut out = String::new();
+
+    let total_ms = ticks.last().map(|(t, _)| *t).unwrap_or(0);
+    let _ = writeln!(out, "Run report for {}", trace_path.display());
+    let _ = writeln!(out, "Total time: {:.1} s over {} ticks", total_ms as f32 / 1000., ticks.len());
+
+    let _ = writeln!(out, "\nTime per phase:");
+    match &log {
+        Some((_, log)) => {
+            for (phase, seconds) in phase_times(log) {
+                let _ = writeln!(out, "  {:<16} {:>8.1} s", phase, seconds);
+            }
+        }
+        // Ticks are only recorded during line follow, so they can't tell the phases apart
+        None => {
+            let _ = writeln!(out, "  (needs the run's log)");
+        }
+    }
+
+    let mut loop_times: Vec<u64> = ticks.windows(2).map(|pair| pair[1].0 - pair[0].0).collect();
+
+    let count = |wanted: fn(&TraceEvent) -> bool| -> usize {
+        ticks.iter().map(|(_, tick)| tick.events.iter().filter(|e| wanted(e)).count()).sum()
+    };
+    let _ = writeln!(out, "\nEvents:");
+    let _ = writeln!(out, "  Green turns:         {}", count(|e| matches!(e, TraceEvent::Green(_))));
+    let _ = writeln!(out, "  Water tower detours: {}", count(|e| *e == TraceEvent::WaterTower));
+    let _ = writeln!(out, "  Line losses:         {}", line_losses(&trace));
+    let _ = writeln!(out, "  Stalls:              {}", count(|e| *e == TraceEvent::Stall));
+    let _ = writeln!(out, "  Slips:               {}", count(|e| *e == TraceEvent::Slip));
+    let _ = writeln!(out, "  Edge following:      {} ticks", count(|e| matches!(e, TraceEvent::Edge(_))));
+    let _ = writeln!(out, "  Parameter changes:   {}", count(|e| matches!(e, TraceEvent::Set(..))));
+
+    if !loop_times.is_empty() {
+        loop_times.sort_unstable();
+        let mean = loop_times.iter().sum::<u64>() as f32 / loop_times.len() as f32;
+        let p95 = loop_times[(loop_times.len() - 1) * 95 / 100];
+        let _ = writeln!(out, "\nLoop timing (ms):");
+        let _ = writeln!(
+            out,
+            "  min {} / mean {:.1} / p95 {} / max {}",
+            loop_times[0],
+            mean,
+            p95,
+            loop_times[loop_times.len() - 1]
+        );
+    }
+
+    let _ = writeln!(out, "\nParameters and calibration:");
+    let params = &trace.parameters;
+    let _ = writeln!(
+        out,
+        "  kp {} / tick {} ms / speed {} / green threshold {}",
+        params.kp, params.tick, params.targeted_speed, params.green_threshold
+    );
+    let _ = writeln!(out, "  Left: {}, Right: {}", trace.calibration.left, trace.calibration.right);
+    if let Some(volts) = trace.battery_volts {
+        let _ = writeln!(out, "  Battery: {:.2} V", volts);
+    }
+
+    if let Some((log_path, log)) = &log {
+        summarise_log(&mut out, log_path, log);
+    }
+
+    let svg_path = trace_path.with_extension("svg");
+    fs::write(&svg_path, plot(ticks)).map_err(|e| format!("{}: {}", svg_path.display(), e))?;
+    let _ = writeln!(out, "\nPlot written to {}", svg_path.display());
+
+    return Ok(out);
+}
+
+// Ticks where no sensor saw the line. The pair straddles the line, so it has lost it only
+// when both read the surface; a wider array is placed the same way the run did.
+fn line_losses(trace: &Trace) -> usize {
+    let near_surface = |reading: &RGB, surface: &RGB| (reading.reflectivity() - surface.reflectivity()).abs() < LOST_LINE_BAND;
+    let mut losses = 0;
+    let mut run = 0;
+    for (_, tick) in &trace.ticks {
+        let lineless = if trace.offsets.len() > 2 {
+            let samples =
+                line_sensing::samples(&trace.offsets, &trace.calibration, &tick.left_raw, &tick.right_raw, &tick.extra_raw);
+            !line_sensing::estimate(&samples).sees_line()
+        } else {
+            near_surface(&tick.left_raw, &trace.calibration.left) && near_surface(&tick.right_raw, &trace.calibration.right)
+        };
+        let lineless = tick.phase == Phase::LineFollow && lineless;
+        run = if lineless { run + 1 } else { 0 };
+        if run == LOST_LINE_TICKS {
+            losses += 1;
+        }
+    }
+    return losses;
+}
+
+// Time from each log line to the next, charged to the phase the line is tagged with. Every
+// phase change is logged as it happens, so nothing between two lines is in another phase.
+fn phase_times(log: &str) -> Vec<(String, f32)> {
+    let mut times = Vec::<(String, f32)>::new();
+    let mut last: Option<(f32, &str)> = None;
+    for (seconds, phase) in log.lines().filter_map(log_line_phase) {
+        if let Some((since, previous)) = last {
+            let elapsed = (seconds - since).max(0.);
+            match times.iter_mut().find(|(name, _)| name == previous) {
+                Some((_, total)) => *total += elapsed,
+                None => times.push((previous.to_string(), elapsed)),
+            }
+        }
+        last = Some((seconds, phase));
+    }
+    return times;
+}
+
+// `[   12.345] (?) [LINE FOLLOW] » ...` as seconds and phase
+fn log_line_phase(line: &str) -> Option<(f32, &str)> {
+    let (stamp, rest) = line.strip_prefix('[')?.split_once(']')?;
+    let (tagged, _) = rest.split_once("] »")?;
+    return Some((stamp.trim().parse().ok()?, tagged.rsplit_once('[')?.1));
+}
+
+fn summarise_log(out: &mut String, log_path: &Path, log: &str) {
+    let problems: Vec<&str> = log
+        .lines()
+        .filter(|line| line.contains("(!)") || line.contains("(x)"))
+        .collect();
+
+    let _ = writeln!(out, "\nWarnings and errors from {} ({}):", log_path.display(), problems.len());
+    for line in problems {
+        let _ = writeln!(out, "  {}", line);
+    }
+}
+
+// Two stacked panels sharing a time axis: heading on top, commanded motor speeds below
+fn plot(ticks: &[(u64, TraceTick)]) -> String {
+    let end = ticks.last().map(|(t, _)| *t).unwrap_or(0).max(1) as f32;
+    let x = |t: u64| MARGIN + (t as f32 / end) * (SVG_WIDTH - 2. * MARGIN);
+    let height = 2. * PANEL_HEIGHT + 3. * MARGIN;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="monospace" font-size="12">"##,
+        w = SVG_WIDTH,
+        h = height
+    );
+    let _ = writeln!(svg, r##"<rect width="100%" height="100%" fill="white"/>"##);
+
+    let panels: [(&str, f32, Vec<Series>); 2] = [
+        ("heading", MARGIN, vec![("black", ticks.iter().map(|(_, t)| t.heading).collect())]),
+        (
+            "motor speed (left red, right blue)",
+            2. * MARGIN + PANEL_HEIGHT,
+            vec![
+                ("red", ticks.iter().map(|(_, t)| t.left_speed as f32).collect()),
+                ("blue", ticks.iter().map(|(_, t)| t.right_speed as f32).collect()),
+            ],
+        ),
+    ];
+
+    for (title, top, series) in panels.iter() {
+        let limit = series
+            .iter()
+            .flat_map(|(_, values)| values.iter())
+            .fold(1f32, |max, v| max.max(v.abs()));
+        let y = |v: f32| top + PANEL_HEIGHT / 2. - (v / limit) * (PANEL_HEIGHT / 2.);
+
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="#ccc"/>"##,
+            MARGIN,
+            top,
+            SVG_WIDTH - 2. * MARGIN,
+            PANEL_HEIGHT
+        );
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{}" y1="{m}" x2="{}" y2="{m}" stroke="#eee"/>"##,
+            MARGIN,
+            SVG_WIDTH - MARGIN,
+            m = y(0.)
+        );
+        let _ = writeln!(svg, r##"<text x="{}" y="{}">{} (±{:.0})</text>"##, MARGIN, top - 5., title, limit);
+
+        for (colour, values) in series {
+            let points: Vec<String> = ticks
+                .iter()
+                .zip(values)
+                .map(|((t, _), v)| format!("{:.1},{:.1}", x(*t), y(*v)))
+                .collect();
+            let _ = writeln!(
+                svg,
+                r##"<polyline fill="none" stroke="{}" stroke-width="1" points="{}"/>"##,
+                colour,
+                points.join(" ")
+            );
+        }
+    }
+
+    // Mark events across both panels
+    for (t, tick) in ticks {
+        for event in &tick.events {
+            let colour = match event {
+                TraceEvent::Green(_) => "green",
+                TraceEvent::WaterTower => "teal",
+                TraceEvent::Stall | TraceEvent::Slip => "orange",
+                TraceEvent::Set(..) => "purple",
+                // Every tick while it lasts, too many to mark
+                TraceEvent::Edge(_) => continue,
+            };
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="{}" stroke-dasharray="4 2"/>"##,
+                MARGIN,
+                height - MARGIN,
+                colour,
+                x = x(*t)
+            );
+        }
+    }
+
+    let _ = writeln!(
+        svg,
+        r##"<text x="{}" y="{}">0 s</text><text x="{}" y="{}" text-anchor="end">{:.1} s</text>"##,
+        MARGIN,
+        height - MARGIN / 2.,
+        SVG_WIDTH - MARGIN,
+        height - MARGIN / 2.,
+        end / 1000.
+    );
+    svg.push_str("</svg>\n");
+    return svg;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase_times_split_the_log_at_phase_changes() {
+        let log = "\
+[    0.000] (?) [STARTUP] » Loaded config
+[    1.000] (?) [CALIBRATION] » Entered CALIBRATION
+[    4.500] (?) [LINE FOLLOW] » Entered LINE FOLLOW
+[   10.000] (?) [LINE FOLLOW] » Avoiding water tower
+[   10.000] (?) [WATER TOWER] » Entered WATER TOWER
+[   13.000] (?) [LINE FOLLOW] » Entered LINE FOLLOW
+continued from a multi-line message
+[   20.000] (?) [SHUTDOWN] » Entered SHUTDOWN";
+        let times = phase_times(log);
+        let names: Vec<&str> = times.iter().map(|(phase, _)| phase.as_str()).collect();
+        assert_eq!(names, ["STARTUP", "CALIBRATION", "LINE FOLLOW", "WATER TOWER"]);
+        let seconds: Vec<f32> = times.iter().map(|(_, seconds)| *seconds).collect();
+        assert_eq!(seconds, [1., 3.5, 12.5, 3.]);
+    }
+}
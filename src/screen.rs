@@ -0,0 +1,156 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Where and how to draw. The defaults match the EV3 LCD under ev3dev stretch; point
+/// `framebuffer` at a regular file to render somewhere you can look at off the brick.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenConfig {
+    pub framebuffer: PathBuf,
+    pub width: usize,
+    pub height: usize,
+    /// 32 (XRGB) or 1 (monochrome, set bit is black)
+    pub bits_per_pixel: usize,
+}
+
+impl Default for ScreenConfig {
+    fn default() -> Self {
+        return Self {
+            framebuffer: "/dev/fb0".into(),
+            width: 178,
+            height: 128,
+            bits_per_pixel: 32,
+        };
+    }
+}
+
+// Classic 5x7 font, one byte per column with the top row in bit 0, from ' ' to '_'
+const FONT: [[u8; 5]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00], [0x08, 0x2A, 0x1C, 0x2A, 0x08], [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00], [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3E],
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], [0x7F, 0x09, 0x09, 0x01, 0x01],
+    [0x3E, 0x41, 0x41, 0x51, 0x32], [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x04, 0x02, 0x7F], [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x7F, 0x20, 0x18, 0x20, 0x7F], [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7F, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+];
+
+// Glyphs are 5 pixels wide plus a column of spacing, 7 tall plus a row of spacing
+pub const CHAR_WIDTH: usize = 6;
+pub const LINE_HEIGHT: usize = 9;
+
+/// A black-on-white drawing surface, written to the framebuffer in one go by `present`.
+pub struct Screen {
+    config: ScreenConfig,
+    // true is black
+    pixels: Vec<bool>,
+}
+
+impl Screen {
+    pub fn new(config: ScreenConfig) -> Self {
+        let pixels = vec![false; config.width * config.height];
+        return Self { config, pixels };
+    }
+
+    pub fn width(&self) -> usize {
+        return self.config.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.config.height;
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|p| *p = false);
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, black: bool) {
+        if x < self.config.width && y < self.config.height {
+            self.pixels[y * self.config.width + x] = black;
+        }
+    }
+
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, black: bool) {
+        for row in y..y + height {
+            for col in x..x + width {
+                self.set(col, row, black);
+            }
+        }
+    }
+
+    /// Draws `text` with its top left corner at (x, y), `scale` times the font size.
+    /// Lowercase is drawn as uppercase; anything else the font lacks as '?'.
+    pub fn text(&mut self, x: usize, y: usize, text: &str, scale: usize, inverted: bool) {
+        for (i, c) in text.chars().enumerate() {
+            let code = c.to_ascii_uppercase() as usize;
+            let glyph = FONT.get(code.wrapping_sub(0x20)).unwrap_or(&FONT[('?' as usize) - 0x20]);
+            let left = x + i * CHAR_WIDTH * scale;
+            for col in 0..CHAR_WIDTH {
+                // The last column is spacing
+                let bits = glyph.get(col).copied().unwrap_or(0);
+                for row in 0..8 {
+                    let lit = row < 7 && bits & (1 << row) != 0;
+                    for dx in 0..scale {
+                        for dy in 0..scale {
+                            self.set(left + col * scale + dx, y + row * scale + dy, lit != inverted);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the picture out to the framebuffer.
+    pub fn present(&self) -> io::Result<()> {
+        let (width, height) = (self.config.width, self.config.height);
+        let bytes = match self.config.bits_per_pixel {
+            32 => {
+                let mut bytes = Vec::with_capacity(width * height * 4);
+                for &black in &self.pixels {
+                    bytes.extend_from_slice(if black { &[0, 0, 0, 0] } else { &[0xFF, 0xFF, 0xFF, 0] });
+                }
+                bytes
+            }
+            1 => {
+                let stride = width.div_ceil(8);
+                let mut bytes = vec![0u8; stride * height];
+                for (i, &black) in self.pixels.iter().enumerate() {
+                    if black {
+                        bytes[(i / width) * stride + (i % width) / 8] |= 1 << (i % width % 8);
+                    }
+                }
+                bytes
+            }
+            bpp => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported framebuffer depth {}", bpp),
+                ))
+            }
+        };
+
+        let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(&self.config.framebuffer)?;
+        file.seek(SeekFrom::Start(0))?;
+        return file.write_all(&bytes);
+    }
+}
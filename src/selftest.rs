@@ -0,0 +1,124 @@
+use ev3dev_lang_rust::{
+    motors::{LargeMotor, MediumMotor},
+    sensors::{ColorSensor, UltrasonicSensor},
+    Ev3Error, Ev3Result,
+};
+
+use crate::{
+    config::{self, Config},
+    motion::BlockingMotor,
+    Icarus,
+};
+
+// How far to jog each motor, and the least encoder movement that counts as working
+const JOG_ROTATIONS: f32 = 0.1;
+const MIN_JOG_COUNTS: i32 = 10;
+const JOG_SPEED: i32 = 150;
+
+struct Check {
+    device: &'static str,
+    port: String,
+    passed: bool,
+    detail: String,
+}
+
+/// Checks that every configured device is present and responding, prints a
+/// pass/fail table, and returns whether everything passed.
+pub fn selftest(config: &Config) -> bool {
+    let ports = &config.ports;
+    let mut checks = Vec::<Check>::new();
+    let mut check = |device: &'static str, port: &str, result: Result<String, String>| {
+        checks.push(Check {
+            device,
+            port: port.to_string(),
+            passed: result.is_ok(),
+            detail: result.unwrap_or_else(|e| e),
+        });
+    };
+
+    check("Left light", &ports.left_light, colour_sensor(&ports.left_light));
+    check("Right light", &ports.right_light, colour_sensor(&ports.right_light));
+    for extra in &config.line_sensors.extra {
+        check("Extra light", &extra.port, colour_sensor(&extra.port));
+    }
+    check("Ultrasonic", &ports.ultrasonic, ultrasonic(&ports.ultrasonic));
+    check("Left motor", &ports.left_motor, large_motor(&ports.left_motor));
+    check("Right motor", &ports.right_motor, large_motor(&ports.right_motor));
+    check("Claw vertical", &ports.claw_vert, large_motor(&ports.claw_vert));
+    check("Claw horizontal", &ports.claw_horiz, medium_motor(&ports.claw_horiz));
+
+    println!("DEVICE           PORT  RESULT DETAIL");
+    for check in &checks {
+        println!(
+            "{:<16} {:<5} {:<6} {}",
+            check.device,
+            check.port,
+            if check.passed { "PASS" } else { "FAIL" },
+            check.detail
+        );
+    }
+
+    let failed = checks.iter().filter(|c| !c.passed).count();
+    if failed == 0 {
+        Icarus::info("Self test passed".to_string());
+    } else {
+        Icarus::error(format!("Self test failed: {} of {} checks", failed, checks.len()));
+    }
+    return failed == 0;
+}
+
+fn describe(e: Ev3Error) -> String {
+    return match e {
+        Ev3Error::NotConnected { .. } => "not connected".to_string(),
+        Ev3Error::MultipleMatches { .. } => "multiple matching devices".to_string(),
+        Ev3Error::InternalError { msg } => msg,
+    };
+}
+
+fn colour_sensor(port: &str) -> Result<String, String> {
+    let sensor = ColorSensor::get(config::sensor_port(port)?).map_err(describe)?;
+    let read = || -> Ev3Result<(i32, i32, i32)> {
+        sensor.set_mode_rgb_raw()?;
+        return sensor.get_rgb();
+    };
+    let (r, g, b) = read().map_err(describe)?;
+    if r == 0 && g == 0 && b == 0 {
+        return Err("reads all zero, is the sensor lit?".to_string());
+    }
+    return Ok(format!("RGB {} {} {}", r, g, b));
+}
+
+fn ultrasonic(port: &str) -> Result<String, String> {
+    let sensor = UltrasonicSensor::get(config::sensor_port(port)?).map_err(describe)?;
+    let read = || -> Ev3Result<f32> {
+        sensor.set_mode_us_dist_cm()?;
+        return sensor.get_distance_centimeters();
+    };
+    let distance = read().map_err(describe)?;
+    return Ok(format!("{:.1} cm", distance));
+}
+
+fn large_motor(port: &str) -> Result<String, String> {
+    return jog(&LargeMotor::get(config::motor_port(port)?).map_err(describe)?);
+}
+
+fn medium_motor(port: &str) -> Result<String, String> {
+    return jog(&MediumMotor::get(config::motor_port(port)?).map_err(describe)?);
+}
+
+// Nudges the motor forward and back again, checking the encoder followed
+fn jog<M: BlockingMotor>(motor: &M) -> Result<String, String> {
+    let start = motor.position().map_err(describe)?;
+    motor
+        .run_rotations(JOG_ROTATIONS, JOG_SPEED)
+        .map_err(|e| format!("jog failed: {}", e))?;
+    let moved = motor.position().map_err(describe)? - start;
+    motor
+        .run_rotations(-JOG_ROTATIONS, JOG_SPEED)
+        .map_err(|e| format!("return failed: {}", e))?;
+
+    if moved.abs() < MIN_JOG_COUNTS {
+        return Err(format!("encoder only moved {} counts", moved));
+    }
+    return Ok(format!("encoder moved {} counts", moved));
+}
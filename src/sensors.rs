@@ -0,0 +1,193 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use ev3dev_lang_rust::{
+    sensors::{ColorSensor, SensorPort, UltrasonicSensor},
+    Device, Ev3Result, Port,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Context, IcarusResult},
+    line_follow::{Side, RGB},
+    Icarus, LineFollowRobot,
+};
+
+/// What the ultrasonic reads with nothing in range; used in its place while it is missing.
+pub const OUT_OF_RANGE: f32 = 255.;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorConfig {
+    /// Tries per read before the sensor is checked for
+    pub read_attempts: u32,
+    pub retry_delay_ms: u64,
+    /// How often to look for a sensor that has gone
+    pub reacquire_interval_ms: u64,
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        return Self {
+            read_attempts: 3,
+            retry_delay_ms: 5,
+            reacquire_interval_ms: 500,
+        };
+    }
+}
+
+/// Whether a sensor is still there, and where to look for it if not.
+pub struct SensorLink {
+    pub port: SensorPort,
+    lost_since: Option<Instant>,
+    last_attempt: Instant,
+}
+
+impl SensorLink {
+    pub fn new(port: SensorPort) -> Self {
+        return Self {
+            port,
+            lost_since: None,
+            last_attempt: Instant::now(),
+        };
+    }
+
+    pub fn is_lost(&self) -> bool {
+        return self.lost_since.is_some();
+    }
+}
+
+pub struct SensorLinks {
+    pub left: SensorLink,
+    pub right: SensorLink,
+    pub ultrasonic: SensorLink,
+    /// The extra line sensors, in config order
+    pub extra: Vec<SensorLink>,
+    pub config: SensorConfig,
+}
+
+impl SensorLinks {
+    pub fn new(left: SensorPort, right: SensorPort, ultrasonic: SensorPort, config: SensorConfig) -> Self {
+        return Self {
+            left: SensorLink::new(left),
+            right: SensorLink::new(right),
+            ultrasonic: SensorLink::new(ultrasonic),
+            extra: Vec::new(),
+            config,
+        };
+    }
+}
+
+fn retry<T>(config: &SensorConfig, mut read: impl FnMut() -> Ev3Result<T>) -> Ev3Result<T> {
+    let mut attempt = 1;
+    loop {
+        match read() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= config.read_attempts => return Err(e),
+            Err(_) => {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(config.retry_delay_ms));
+            }
+        }
+    }
+}
+
+// Reads through retries. A sensor that still fails is reopened if it is there and marked
+// lost if not; either way the tick goes without (`None`). Lost sensors are looked for
+// again every so often and swapped back in when they turn up.
+fn read_or_recover<D: Device, T>(
+    link: &mut SensorLink,
+    device: &mut D,
+    config: &SensorConfig,
+    name: &'static str,
+    operation: &'static str,
+    read: impl Fn(&D) -> Ev3Result<T>,
+    open: impl Fn(SensorPort) -> Ev3Result<D>,
+) -> IcarusResult<Option<T>> {
+    let port = link.port.address();
+    if let Some(since) = link.lost_since {
+        if link.last_attempt.elapsed() < Duration::from_millis(config.reacquire_interval_ms) {
+            return Ok(None);
+        }
+        link.last_attempt = Instant::now();
+        match open(link.port) {
+            Ok(found) => {
+                *device = found;
+                link.lost_since = None;
+                Icarus::info(format!("{} back on {} after {:.1} s", name, port, since.elapsed().as_secs_f32()));
+            }
+            Err(_) => return Ok(None),
+        }
+    }
+
+    let error = match retry(config, || read(device)).context(device, name, operation) {
+        Ok(value) => return Ok(Some(value)),
+        Err(error) => error,
+    };
+    match open(link.port) {
+        // Still plugged in, so a fresh handle gets another go next tick
+        Ok(reopened) => {
+            *device = reopened;
+            Icarus::warn(format!("{}, reopened it", error));
+        }
+        Err(_) => {
+            link.lost_since = Some(Instant::now());
+            link.last_attempt = Instant::now();
+            Icarus::error(format!("{}; it has gone from {}, carrying on without it", error, port));
+        }
+    }
+    return Ok(None);
+}
+
+fn open_colour(port: SensorPort) -> Ev3Result<ColorSensor> {
+    let sensor = ColorSensor::get(port)?;
+    sensor.set_mode_rgb_raw()?;
+    return Ok(sensor);
+}
+
+fn open_ultrasonic(port: SensorPort) -> Ev3Result<UltrasonicSensor> {
+    let sensor = UltrasonicSensor::get(port)?;
+    sensor.set_mode_us_dist_cm()?;
+    return Ok(sensor);
+}
+
+impl LineFollowRobot {
+    /// One colour sensor's RGB, or `None` while it is missing.
+    pub fn read_colour(&mut self, side: Side) -> IcarusResult<Option<RGB>> {
+        let (link, sensor, name) = match side {
+            Side::Left => (&mut self.sensors.left, &mut self.left_light, "left colour sensor"),
+            Side::Right => (&mut self.sensors.right, &mut self.right_light, "right colour sensor"),
+        };
+        let reading = read_or_recover(link, sensor, &self.sensors.config, name, "read RGB", |s| s.get_rgb(), open_colour)?;
+        return Ok(reading.map(RGB::from));
+    }
+
+    /// The `index`th extra line sensor's RGB, or `None` while it is missing.
+    pub fn read_extra(&mut self, index: usize) -> IcarusResult<Option<RGB>> {
+        let reading = read_or_recover(
+            &mut self.sensors.extra[index],
+            &mut self.extra_lights[index],
+            &self.sensors.config,
+            "extra colour sensor",
+            "read RGB",
+            |s| s.get_rgb(),
+            open_colour,
+        )?;
+        return Ok(reading.map(RGB::from));
+    }
+
+    /// Distance in cm, or `None` while the ultrasonic is missing.
+    pub fn read_distance(&mut self) -> IcarusResult<Option<f32>> {
+        return read_or_recover(
+            &mut self.sensors.ultrasonic,
+            &mut self.ultrasonic,
+            &self.sensors.config,
+            "ultrasonic",
+            "read distance",
+            |s| s.get_distance_centimeters(),
+            open_ultrasonic,
+        );
+    }
+}
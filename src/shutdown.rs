@@ -0,0 +1,114 @@
+use std::{mem, panic, process, sync::Mutex, thread};
+
+use ev3dev_lang_rust::{
+    motors::{LargeMotor, MediumMotor},
+    sensors::{ColorSensor, UltrasonicSensor},
+    Ev3Result,
+};
+
+use crate::{
+    indicator::{self, Cue},
+    logging,
+    mission::Phase,
+    Icarus, LineFollowRobot,
+};
+
+// Handles to everything that needs putting back to rest. The ev3dev handles are
+// cheap clones of the same sysfs attributes, so these can live on other threads.
+#[derive(Clone)]
+struct Devices {
+    drive: Vec<LargeMotor>,
+    claw_vert: LargeMotor,
+    claw_horiz: MediumMotor,
+    lights: Vec<ColorSensor>,
+    ultrasonic: UltrasonicSensor,
+}
+
+impl Devices {
+    fn halt(&self) {
+        // Best effort: a half-stopped robot is still better than giving up on the first error
+        if let Err(e) = self.try_halt() {
+            Icarus::error(format!("Failed to halt cleanly: {:?}", e));
+        }
+    }
+
+    fn try_halt(&self) -> Ev3Result<()> {
+        // Brake the wheels so we don't roll off the mat, let the claw go limp
+        for motor in &self.drive {
+            motor.set_stop_action(LargeMotor::STOP_ACTION_BRAKE)?;
+            motor.stop()?;
+        }
+        self.claw_vert.set_stop_action(LargeMotor::STOP_ACTION_COAST)?;
+        self.claw_vert.stop()?;
+        self.claw_horiz.set_stop_action(MediumMotor::STOP_ACTION_COAST)?;
+        self.claw_horiz.stop()?;
+
+        for light in &self.lights {
+            light.set_mode_col_reflect()?;
+        }
+        self.ultrasonic.set_mode_us_dist_cm()?;
+        return Ok(());
+    }
+}
+
+/// Stops every motor and resets sensor modes when dropped, when the program panics,
+/// or when it receives SIGINT/SIGTERM (e.g. killed over SSH).
+///
+/// Must be created on the main thread before any other threads are spawned,
+/// so that they all inherit the blocked signal mask.
+pub struct ShutdownGuard {
+    devices: Devices,
+}
+
+impl ShutdownGuard {
+    pub fn install(robot: &LineFollowRobot) -> Self {
+        let devices = Devices {
+            drive: vec![robot.left_motor.clone(), robot.right_motor.clone()],
+            claw_vert: robot.claw_vert.clone(),
+            claw_horiz: robot.claw_horiz.clone(),
+            lights: [&robot.left_light, &robot.right_light].into_iter().chain(&robot.extra_lights).cloned().collect(),
+            ultrasonic: robot.ultrasonic.clone(),
+        };
+
+        // Device handles aren't Sync, which the panic hook requires
+        let on_panic = Mutex::new(devices.clone());
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // Logged before the phase changes so the panic is tagged with where it happened
+            logging::log_from_panic(&format!("{}", info));
+            Phase::Shutdown.enter();
+            indicator::cue(Cue::Error);
+            // The logger may be held by the panicking thread, so don't block on it here
+            if let Err(e) = on_panic.lock().unwrap_or_else(|e| e.into_inner()).try_halt() {
+                logging::log_from_panic(&format!("Failed to halt cleanly: {:?}", e));
+            }
+            previous_hook(info);
+        }));
+
+        let on_signal = devices.clone();
+        unsafe {
+            let mut signals: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut signals);
+            libc::sigaddset(&mut signals, libc::SIGINT);
+            libc::sigaddset(&mut signals, libc::SIGTERM);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+
+            thread::spawn(move || {
+                let mut signal = 0;
+                libc::sigwait(&signals, &mut signal);
+                Phase::Shutdown.enter();
+                Icarus::warn(format!("Received signal {}, stopping motors", signal));
+                on_signal.halt();
+                process::exit(128 + signal);
+            });
+        }
+
+        return Self { devices };
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        self.devices.halt();
+    }
+}
@@ -0,0 +1,88 @@
+use std::f32::consts::PI;
+
+use crate::{
+    line_follow::{CalibrationProfile, LineFollowController, LineFollowParameters, RGB},
+    mission::Phase,
+    Icarus,
+};
+
+// Robot geometry (cm)
+const WHEEL_DIAMETER: f32 = 5.6;
+const WHEEL_BASE: f32 = 12.;
+const SENSOR_AHEAD: f32 = 6.;
+const SENSOR_SPREAD: f32 = 2.;
+// Field: a black line of this width on white, seen through a sensor spot this wide
+const LINE_WIDTH: f32 = 1.8;
+const SPOT_RADIUS: f32 = 0.6;
+const WHITE: (i32, i32, i32) = (180, 190, 170);
+const BLACK: (i32, i32, i32) = (25, 25, 25);
+// Further than this from the line and the run is over
+const LOST_DISTANCE: f32 = 10.;
+
+/// Line follows a circle of `radius` cm for `seconds` with a simple differential drive
+/// model and reports how well the line was tracked. Returns false if the line was lost.
+pub fn simulate(params: &LineFollowParameters, seconds: f32, radius: f32) -> bool {
+    let mut profile = CalibrationProfile::from((RGB::from(WHITE), RGB::from(WHITE)));
+    profile.black = vec![RGB::from(BLACK).reflectivity(); 2];
+    let mut controller = LineFollowController::new();
+    let dt = params.tick as f32 / 1000.;
+    // The circle is centred on (0, radius), so the robot starts on it facing along it
+    let (mut x, mut y, mut theta) = (0f32, 0f32, 0f32);
+    let off_line = |px: f32, py: f32| ((px * px + (py - radius) * (py - radius)).sqrt() - radius).abs();
+
+    let ticks = (seconds / dt) as u32;
+    let mut squared_error = 0.;
+    let mut worst = 0f32;
+    let mut travelled = 0.;
+
+    Phase::LineFollow.enter();
+    Icarus::info(format!("Simulating {} s on a {} cm radius circle", seconds, radius));
+    for tick in 0..ticks {
+        let sensor = |side: f32| {
+            let sx = x + SENSOR_AHEAD * theta.cos() - side * SENSOR_SPREAD * theta.sin();
+            let sy = y + SENSOR_AHEAD * theta.sin() + side * SENSOR_SPREAD * theta.cos();
+            reading(off_line(sx, sy))
+        };
+        let steering = controller.step(params, &profile, &sensor(1.), &sensor(-1.));
+
+        let wheel = |speed: i32| speed as f32 / 360. * PI * WHEEL_DIAMETER;
+        let (left, right) = (wheel(steering.left_speed), wheel(steering.right_speed));
+        let speed = (left + right) / 2.;
+        theta += (right - left) / WHEEL_BASE * dt;
+        x += speed * theta.cos() * dt;
+        y += speed * theta.sin() * dt;
+        travelled += speed.abs() * dt;
+
+        let error = off_line(x, y);
+        squared_error += error * error;
+        worst = worst.max(error);
+        if error > LOST_DISTANCE {
+            Icarus::warn(format!("Lost the line after {:.1} s", tick as f32 * dt));
+            return false;
+        }
+        if tick % (1. / dt).max(1.) as u32 == 0 {
+            Icarus::debug(format!(
+                "t {:>5.1} s  off line {:>5.2} cm  speeds {}/{}",
+                tick as f32 * dt,
+                error,
+                steering.left_speed,
+                steering.right_speed
+            ));
+        }
+    }
+
+    Icarus::info(format!(
+        "Travelled {:.0} cm, RMS off line {:.2} cm, worst {:.2} cm",
+        travelled,
+        (squared_error / ticks.max(1) as f32).sqrt(),
+        worst
+    ));
+    return true;
+}
+
+// Blend white and black by how much of the sensor spot covers the line
+fn reading(distance_to_centre: f32) -> RGB {
+    let black = ((LINE_WIDTH / 2. + SPOT_RADIUS - distance_to_centre) / (2. * SPOT_RADIUS)).clamp(0., 1.);
+    let mix = |w: i32, b: i32| (w as f32 * (1. - black) + b as f32 * black) as i32;
+    return RGB::from((mix(WHITE.0, BLACK.0), mix(WHITE.1, BLACK.1), mix(WHITE.2, BLACK.2)));
+}
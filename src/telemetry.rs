@@ -0,0 +1,53 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::trace::{TraceTick, COLUMNS};
+
+// Repeat the column header this often so a receiver started mid-run can label the stream
+const HEADER_INTERVAL: u32 = 100;
+
+/// Fires one datagram per control tick at a listening laptop. Sends are non-blocking and
+/// their errors ignored, so nobody listening (or a flaky network) never slows the loop.
+///
+/// Each datagram is a trace row (see `trace::COLUMNS`) followed by the loop time in ms.
+/// Lines starting with `#` carry the column header.
+pub struct TelemetryPublisher {
+    socket: UdpSocket,
+    target: SocketAddr,
+    started: Instant,
+    sent: u32,
+}
+
+impl TelemetryPublisher {
+    /// `target` is anything that resolves to a socket address, e.g. `10.0.0.2:5005`.
+    pub fn new(target: &str) -> io::Result<Self> {
+        let target = target
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "telemetry target did not resolve"))?;
+        let socket = UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_nonblocking(true)?;
+        return Ok(Self {
+            socket,
+            target,
+            started: Instant::now(),
+            sent: 0,
+        });
+    }
+
+    pub fn publish(&mut self, tick: &TraceTick, loop_time: Duration) {
+        if self.sent.is_multiple_of(HEADER_INTERVAL) {
+            self.send(&format!("# {},loop_ms", COLUMNS));
+        }
+        let row = tick.to_row(self.started.elapsed().as_millis() as u64);
+        self.send(&format!("{},{:.1}", row, loop_time.as_secs_f32() * 1000.));
+        self.sent = self.sent.wrapping_add(1);
+    }
+
+    fn send(&self, datagram: &str) {
+        let _ = self.socket.send_to(datagram.as_bytes(), self.target);
+    }
+}
@@ -0,0 +1,489 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    line_follow::{CalibrationProfile, EdgeParameters, LineFollowParameters, Parameter, Side, RGB},
+    mission::Phase,
+    Icarus, LineFollowRobot,
+};
+
+// Flush roughly once a second at the usual tick so a crash loses little
+const FLUSH_INTERVAL: u32 = 20;
+
+pub const COLUMNS: &str = "t_ms,phase,events,left_r,left_g,left_b,right_r,right_g,right_b,\
+left_cal_r,left_cal_g,left_cal_b,right_cal_r,right_cal_g,right_cal_b,\
+heading,left_speed,right_speed,ultrasonic,left_position,right_position,extra_raw";
+
+// Traces from before extra line sensors end at right_position
+const EXTRA_COLUMN: &str = ",extra_raw";
+
+/// Something out of the ordinary that happened during a tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceEvent {
+    WaterTower,
+    Green(Side),
+    Stall,
+    Slip,
+    /// Steered on this side's colour sensor alone
+    Edge(Side),
+    /// A parameter was changed remotely to this value (as run, after battery compensation)
+    Set(Parameter, f32),
+}
+
+impl TraceEvent {
+    fn code(&self) -> String {
+        let code = match self {
+            TraceEvent::WaterTower => "W",
+            TraceEvent::Green(Side::Left) => "GL",
+            TraceEvent::Green(Side::Right) => "GR",
+            TraceEvent::Stall => "ST",
+            TraceEvent::Slip => "SL",
+            TraceEvent::Edge(Side::Left) => "EL",
+            TraceEvent::Edge(Side::Right) => "ER",
+            // e.g. `kp=3.5`
+            TraceEvent::Set(parameter, value) => return format!("{}={}", parameter, value),
+        };
+        return code.to_string();
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        if let Some((parameter, value)) = code.split_once('=') {
+            return Some(TraceEvent::Set(parameter.parse().ok()?, value.parse().ok()?));
+        }
+        return match code {
+            "W" => Some(TraceEvent::WaterTower),
+            "GL" => Some(TraceEvent::Green(Side::Left)),
+            "GR" => Some(TraceEvent::Green(Side::Right)),
+            "ST" => Some(TraceEvent::Stall),
+            "SL" => Some(TraceEvent::Slip),
+            "EL" => Some(TraceEvent::Edge(Side::Left)),
+            "ER" => Some(TraceEvent::Edge(Side::Right)),
+            _ => None,
+        };
+    }
+}
+
+/// Everything `line_follow()` saw and did in one control tick.
+#[derive(Clone)]
+pub struct TraceTick {
+    pub phase: Phase,
+    pub events: Vec<TraceEvent>,
+    pub left_raw: RGB,
+    pub right_raw: RGB,
+    /// The extra line sensors in config order, `None` while one is missing
+    pub extra_raw: Vec<Option<RGB>>,
+    pub left_calibrated: RGB,
+    pub right_calibrated: RGB,
+    pub heading: f32,
+    pub left_speed: i32,
+    pub right_speed: i32,
+    pub ultrasonic: f32,
+    pub left_position: i32,
+    pub right_position: i32,
+}
+
+impl TraceTick {
+    /// The tick as a CSV row matching `COLUMNS`.
+    pub fn to_row(&self, t_ms: u64) -> String {
+        return format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{:.2},{},{},{:.1},{},{},{}",
+            t_ms,
+            phase_code(self.phase),
+            self.events.iter().map(|e| e.code()).collect::<Vec<_>>().join("|"),
+            self.left_raw.r,
+            self.left_raw.g,
+            self.left_raw.b,
+            self.right_raw.r,
+            self.right_raw.g,
+            self.right_raw.b,
+            self.left_calibrated.r,
+            self.left_calibrated.g,
+            self.left_calibrated.b,
+            self.right_calibrated.r,
+            self.right_calibrated.g,
+            self.right_calibrated.b,
+            self.heading,
+            self.left_speed,
+            self.right_speed,
+            self.ultrasonic,
+            self.left_position,
+            self.right_position,
+            // `r/g/b` per sensor, `-` for a missing one
+            self.extra_raw
+                .iter()
+                .map(|rgb| match rgb {
+                    Some(rgb) => format!("{}/{}/{}", rgb.r, rgb.g, rgb.b),
+                    None => "-".to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("|"),
+        );
+    }
+}
+
+/// Writes one CSV row per control tick, preceded by `#` comment lines recording
+/// the parameters and calibration the run used.
+pub struct TraceRecorder {
+    out: BufWriter<File>,
+    started: Instant,
+    unflushed: u32,
+    // Events from between ticks, written with the next one
+    pending: Vec<TraceEvent>,
+}
+
+impl TraceRecorder {
+    /// Creates `trace-<unix time>.csv` in `dir`.
+    pub fn create(
+        dir: &Path,
+        params: &LineFollowParameters,
+        calibration: &CalibrationProfile,
+        offsets: &[f32],
+        battery_volts: Option<f32>,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0);
+        let path: PathBuf = dir.join(format!("trace-{}.csv", stamp));
+
+        let mut out = BufWriter::with_capacity(64 * 1024, File::create(&path)?);
+        writeln!(out, "# icarus trace v1")?;
+        writeln!(out, "# kp={}", params.kp)?;
+        writeln!(out, "# tick={}", params.tick)?;
+        writeln!(out, "# targeted_speed={}", params.targeted_speed)?;
+        writeln!(out, "# green_threshold={}", params.green_threshold)?;
+        writeln!(out, "# edge_kp={}", params.edge.kp)?;
+        writeln!(out, "# edge_targeted_speed={}", params.edge.targeted_speed)?;
+        writeln!(out, "# edge_fraction={}", params.edge.edge_fraction)?;
+        writeln!(out, "# calibration_left={},{},{}", calibration.left.r, calibration.left.g, calibration.left.b)?;
+        writeln!(out, "# calibration_right={},{},{}", calibration.right.r, calibration.right.g, calibration.right.b)?;
+        if !calibration.extra.is_empty() {
+            let extra: Vec<String> = calibration.extra.iter().map(|rgb| format!("{},{},{}", rgb.r, rgb.g, rgb.b)).collect();
+            writeln!(out, "# calibration_extra={}", extra.join(";"))?;
+        }
+        let black: Vec<String> = calibration.black.iter().map(|black| black.to_string()).collect();
+        writeln!(out, "# calibration_black={}", black.join(","))?;
+        if let Some(classifier) = &calibration.classifier {
+            let json = serde_json::to_string(classifier).map_err(io::Error::other)?;
+            writeln!(out, "# colour_classifier={}", json)?;
+        }
+        let offsets: Vec<String> = offsets.iter().map(|offset| offset.to_string()).collect();
+        writeln!(out, "# line_offsets={}", offsets.join(","))?;
+        if let Some(volts) = battery_volts {
+            writeln!(out, "# battery_volts={:.2}", volts)?;
+        }
+        writeln!(out, "{}", COLUMNS)?;
+        out.flush()?;
+
+        Icarus::info(format!("Recording trace to {}", path.display()));
+        return Ok(Self {
+            out,
+            started: Instant::now(),
+            unflushed: 0,
+            pending: Vec::new(),
+        });
+    }
+
+    /// Records an event that happened outside the control loop with the next tick.
+    pub fn note(&mut self, event: TraceEvent) {
+        self.pending.push(event);
+    }
+
+    /// Appends a tick. A failed write disables nothing; it is up to the caller whether to carry on.
+    pub fn record(&mut self, tick: &TraceTick) -> io::Result<()> {
+        let t_ms = self.started.elapsed().as_millis() as u64;
+        if self.pending.is_empty() {
+            writeln!(self.out, "{}", tick.to_row(t_ms))?;
+        } else {
+            let mut tick = tick.clone();
+            tick.events.splice(0..0, self.pending.drain(..));
+            writeln!(self.out, "{}", tick.to_row(t_ms))?;
+        }
+
+        self.unflushed += 1;
+        if self.unflushed >= FLUSH_INTERVAL {
+            self.out.flush()?;
+            self.unflushed = 0;
+        }
+        return Ok(());
+    }
+}
+
+impl LineFollowRobot {
+    /// Starts a new trace file, if tracing is enabled and we are calibrated. Called whenever
+    /// the calibration changes, so every file's header matches its rows.
+    pub fn restart_trace(&mut self) {
+        self.trace = None;
+        if let (Some(dir), Some(calibration)) = (&self.trace_dir, &self.calibration) {
+            // With battery compensation on, the header has the parameters as actually run
+            let battery_volts = self.battery.as_ref().and_then(|battery| battery.run_volts());
+            let offsets = self.line_sensing.offsets();
+            match TraceRecorder::create(dir, &self.effective_parameters(), calibration, &offsets, battery_volts) {
+                Ok(trace) => self.trace = Some(trace),
+                Err(e) => Icarus::warn(format!("Could not start trace, running without: {}", e)),
+            }
+        }
+    }
+}
+
+impl Drop for TraceRecorder {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+// Phases are written as short codes to keep rows small
+const PHASE_CODES: [(Phase, &str); 6] = [
+    (Phase::Startup, "S"),
+    (Phase::Calibration, "C"),
+    (Phase::LineFollow, "L"),
+    (Phase::WaterTower, "W"),
+    (Phase::ChemicalSpill, "X"),
+    (Phase::Shutdown, "Q"),
+];
+
+pub fn phase_code(phase: Phase) -> &'static str {
+    return PHASE_CODES.iter().find(|(p, _)| *p == phase).map(|(_, code)| *code).unwrap_or("?");
+}
+
+fn phase_from_code(code: &str) -> Option<Phase> {
+    return PHASE_CODES.iter().find(|(_, c)| *c == code).map(|(phase, _)| *phase);
+}
+
+/// A trace read back from disk.
+pub struct Trace {
+    pub parameters: LineFollowParameters,
+    pub calibration: CalibrationProfile,
+    /// Offsets of the line sensors (see `LineSensingConfig::offsets`), empty for older traces
+    pub offsets: Vec<f32>,
+    pub battery_volts: Option<f32>,
+    pub ticks: Vec<(u64, TraceTick)>,
+}
+
+impl Trace {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut header = std::collections::HashMap::<&str, &str>::new();
+        let mut ticks = Vec::new();
+        let mut seen_columns = false;
+
+        for (number, line) in text.lines().enumerate() {
+            let fail = |what: &str| format!("{} line {}: {}", path.display(), number + 1, what);
+            if let Some(comment) = line.strip_prefix("# ") {
+                if let Some((key, value)) = comment.split_once('=') {
+                    header.insert(key, value);
+                }
+                continue;
+            }
+            if !seen_columns {
+                if line != COLUMNS && Some(line) != COLUMNS.strip_suffix(EXTRA_COLUMN) {
+                    return Err(fail("unexpected column layout"));
+                }
+                seen_columns = true;
+                continue;
+            }
+            ticks.push(parse_row(line).ok_or_else(|| fail("malformed row"))?);
+        }
+
+        let field = |key: &str| header.get(key).copied().ok_or(format!("{}: missing {} in header", path.display(), key));
+        let number = |key: &str| -> Result<f32, String> {
+            return field(key)?.parse::<f32>().map_err(|_| format!("{}: bad {} in header", path.display(), key));
+        };
+        let parse_rgb = |key: &str, text: &str| -> Result<RGB, String> {
+            let parts: Vec<i32> = text.split(',').filter_map(|p| p.parse().ok()).collect();
+            if parts.len() != 3 {
+                return Err(format!("{}: bad {} in header", path.display(), key));
+            }
+            return Ok(RGB::from((parts[0], parts[1], parts[2])));
+        };
+        let rgb = |key: &str| parse_rgb(key, field(key)?);
+
+        let mut parameters = LineFollowParameters::new(
+            number("kp")?,
+            number("tick")? as u64,
+            number("targeted_speed")? as i32,
+            number("green_threshold")?,
+        );
+        // Older traces predate edge following
+        if let (Ok(kp), Ok(speed), Ok(fraction)) =
+            (number("edge_kp"), number("edge_targeted_speed"), number("edge_fraction"))
+        {
+            parameters.edge = EdgeParameters {
+                kp,
+                targeted_speed: speed as i32,
+                edge_fraction: fraction,
+            };
+        }
+
+        let mut calibration = CalibrationProfile::from((rgb("calibration_left")?, rgb("calibration_right")?));
+        if let Ok(extra) = field("calibration_extra") {
+            calibration.extra = extra.split(';').map(|text| parse_rgb("calibration_extra", text)).collect::<Result<_, _>>()?;
+        }
+        let numbers = |key: &str| -> Result<Vec<f32>, String> {
+            return match field(key) {
+                Ok(text) => text
+                    .split(',')
+                    .map(|number| number.parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("{}: bad {} in header", path.display(), key)),
+                Err(_) => Ok(Vec::new()),
+            };
+        };
+        // Older traces leave black to be estimated from the surface
+        calibration.black = numbers("calibration_black")?;
+        let offsets = numbers("line_offsets")?;
+        if let Ok(json) = field("colour_classifier") {
+            calibration.classifier = Some(
+                serde_json::from_str(json).map_err(|e| format!("{}: bad colour_classifier in header: {}", path.display(), e))?,
+            );
+        }
+
+        return Ok(Self {
+            parameters,
+            calibration,
+            offsets,
+            battery_volts: number("battery_volts").ok(),
+            ticks,
+        });
+    }
+}
+
+fn parse_row(line: &str) -> Option<(u64, TraceTick)> {
+    let cols: Vec<&str> = line.split(',').collect();
+    let columns = COLUMNS.split(',').count();
+    if cols.len() != columns && cols.len() != columns - 1 {
+        return None;
+    }
+    let int = |i: usize| cols[i].parse::<i32>().ok();
+    let float = |i: usize| cols[i].parse::<f32>().ok();
+    let rgb = |i: usize| Some(RGB::from((int(i)?, int(i + 1)?, int(i + 2)?)));
+    let extra_raw = match cols.get(21) {
+        Some(extra) => extra
+            .split('|')
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry {
+                "-" => Some(None),
+                _ => {
+                    let parts: Vec<i32> = entry.split('/').filter_map(|p| p.parse().ok()).collect();
+                    (parts.len() == 3).then(|| Some(RGB::from((parts[0], parts[1], parts[2]))))
+                }
+            })
+            .collect::<Option<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+    let events = cols[2]
+        .split('|')
+        .filter(|code| !code.is_empty())
+        .map(TraceEvent::from_code)
+        .collect::<Option<Vec<_>>>()?;
+
+    return Some((
+        cols[0].parse().ok()?,
+        TraceTick {
+            phase: phase_from_code(cols[1])?,
+            events,
+            left_raw: rgb(3)?,
+            right_raw: rgb(6)?,
+            extra_raw,
+            left_calibrated: rgb(9)?,
+            right_calibrated: rgb(12)?,
+            heading: float(15)?,
+            left_speed: int(16)?,
+            right_speed: int(17)?,
+            ultrasonic: float(18)?,
+            left_position: int(19)?,
+            right_position: int(20)?,
+        },
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(events: Vec<TraceEvent>, extra_raw: Vec<Option<RGB>>) -> TraceTick {
+        return TraceTick {
+            phase: Phase::LineFollow,
+            events,
+            left_raw: RGB::from((310, 402, 250)),
+            right_raw: RGB::from((40, 52, 31)),
+            extra_raw,
+            left_calibrated: RGB::from((96, 98, 95)),
+            right_calibrated: RGB::from((12, 13, 12)),
+            heading: -0.25,
+            left_speed: 130,
+            right_speed: 70,
+            ultrasonic: 42.5,
+            left_position: 1200,
+            right_position: -35,
+        };
+    }
+
+    fn rgb(rgb: &RGB) -> (i32, i32, i32) {
+        return (rgb.r, rgb.g, rgb.b);
+    }
+
+    #[test]
+    fn rows_read_back_as_written() {
+        let written = tick(
+            vec![
+                TraceEvent::Set(Parameter::Kp, 3.5),
+                TraceEvent::Edge(Side::Left),
+                TraceEvent::Green(Side::Right),
+                TraceEvent::Stall,
+            ],
+            vec![Some(RGB::from((1, 2, 3))), None, Some(RGB::from((400, 500, 600)))],
+        );
+        let row = written.to_row(1234);
+        assert!(row.ends_with(",1/2/3|-|400/500/600"));
+
+        let (t_ms, read) = parse_row(&row).unwrap();
+        assert_eq!(t_ms, 1234);
+        assert_eq!(read.phase, Phase::LineFollow);
+        assert_eq!(read.events, written.events);
+        let extra: Vec<_> = read.extra_raw.iter().map(|extra| extra.as_ref().map(rgb)).collect();
+        assert_eq!(extra, vec![Some((1, 2, 3)), None, Some((400, 500, 600))]);
+        assert_eq!(rgb(&read.left_raw), (310, 402, 250));
+        assert_eq!(rgb(&read.right_calibrated), (12, 13, 12));
+        assert_eq!((read.heading, read.ultrasonic), (-0.25, 42.5));
+        assert_eq!((read.left_position, read.right_position), (1200, -35));
+        assert_eq!(read.to_row(t_ms), row);
+    }
+
+    #[test]
+    fn edge_ticks_with_every_extra_missing_read_back() {
+        let written = tick(vec![TraceEvent::Edge(Side::Right)], vec![None, None]);
+        let row = written.to_row(50);
+        assert!(row.ends_with(",-|-"));
+
+        let (_, read) = parse_row(&row).unwrap();
+        assert_eq!(read.events, vec![TraceEvent::Edge(Side::Right)]);
+        assert!(read.extra_raw.iter().all(Option::is_none));
+        assert_eq!(read.extra_raw.len(), 2);
+    }
+
+    #[test]
+    fn rows_from_before_extra_sensors_still_parse() {
+        let row = tick(Vec::new(), Vec::new()).to_row(0);
+        // Without extra sensors the last column is empty; older traces had no column at all
+        let old = row.strip_suffix(',').unwrap();
+        assert_eq!(old.split(',').count(), COLUMNS.split(',').count() - 1);
+
+        for row in [row.as_str(), old] {
+            let (_, read) = parse_row(row).unwrap();
+            assert!(read.events.is_empty());
+            assert!(read.extra_raw.is_empty());
+            assert_eq!(read.right_position, -35);
+        }
+    }
+
+    #[test]
+    fn malformed_rows_are_refused() {
+        let row = tick(Vec::new(), vec![Some(RGB::from((1, 2, 3)))]).to_row(0);
+        assert!(parse_row(&row.replace("1/2/3", "1/2")).is_none());
+        assert!(parse_row(&row.replace(",L,,", ",L,XX,")).is_none());
+        assert!(parse_row(&format!("{},1", row)).is_none());
+    }
+}
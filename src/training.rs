@@ -0,0 +1,452 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write as _},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    thread::sleep,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buttons::Button,
+    colour::{ColourClass, ColourClassifier, ColourConfig, Lab, Prototype},
+    error::IcarusResult,
+    line_follow::RGB,
+    menu::Menu,
+    Icarus, LineFollowRobot,
+};
+
+const SAMPLES_HEADER: &str = "label,press,sensor,r,g,b,surface_r,surface_g,surface_b";
+// Readings taken from every sensor per press of enter
+const SAMPLES_PER_PRESS: usize = 10;
+const LABELS: [ColourClass; 5] = [
+    ColourClass::White,
+    ColourClass::Black,
+    ColourClass::Green,
+    ColourClass::Red,
+    ColourClass::Silver,
+];
+// Floor on a trained spread, so a class recorded in one spot doesn't reject everything else
+const MIN_SPREAD: f32 = 2.;
+// Samples are held out a press at a time, so neighbouring readings don't test each other
+const FOLDS: usize = 5;
+
+/// One labelled reading, with what its sensor read on the surface at calibration.
+#[derive(Clone)]
+pub struct Sample {
+    pub label: ColourClass,
+    /// Which press of enter recorded it
+    pub press: usize,
+    /// Which line sensor: left, right, then the extras
+    pub sensor: usize,
+    pub reading: RGB,
+    pub surface: RGB,
+}
+
+/// A classifier trained by `icarus train`, as saved for calibration to pick up.
+#[derive(Serialize, Deserialize)]
+pub struct TrainedClassifier {
+    /// The average surface the samples were calibrated on
+    pub surface: RGB,
+    pub samples: usize,
+    pub classifier: ColourClassifier,
+}
+
+impl TrainedClassifier {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+}
+
+pub fn append_samples(path: &Path, samples: &[Sample]) -> io::Result<()> {
+    let new = !path.exists();
+    let mut out = OpenOptions::new().create(true).append(true).open(path)?;
+    if new {
+        writeln!(out, "{}", SAMPLES_HEADER)?;
+    }
+    for sample in samples {
+        let (reading, surface) = (&sample.reading, &sample.surface);
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            sample.label, sample.press, sample.sensor, reading.r, reading.g, reading.b, surface.r, surface.g, surface.b
+        )?;
+    }
+    return Ok(());
+}
+
+pub fn read_samples(path: &Path) -> Result<Vec<Sample>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut samples = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if number == 0 && line == SAMPLES_HEADER {
+            continue;
+        }
+        let fail = || format!("{} line {}: malformed sample", path.display(), number + 1);
+        let cols: Vec<&str> = line.split(',').collect();
+        if cols.len() != SAMPLES_HEADER.split(',').count() {
+            return Err(fail());
+        }
+        let label = LABELS.into_iter().find(|label| label.to_string() == cols[0]).ok_or_else(fail)?;
+        let int = |i: usize| cols[i].parse::<i32>().map_err(|_| fail());
+        samples.push(Sample {
+            label,
+            press: int(1)? as usize,
+            sensor: int(2)? as usize,
+            reading: RGB::from((int(3)?, int(4)?, int(5)?)),
+            surface: RGB::from((int(6)?, int(7)?, int(8)?)),
+        });
+    }
+    return Ok(samples);
+}
+
+/// Fits a Gaussian to each label's readings in Lab (relative to their own surface), giving
+/// the classifier the same shape as one built from `colour.prototypes`.
+pub fn train(samples: &[&Sample], config: &ColourConfig) -> Result<ColourClassifier, String> {
+    let mut prototypes = Vec::new();
+    for label in LABELS {
+        let labs: Vec<[f32; 3]> = samples
+            .iter()
+            .filter(|sample| sample.label == label)
+            .map(|sample| {
+                let lab = sample.reading.lab(&sample.surface);
+                [lab.l, lab.a, lab.b]
+            })
+            .collect();
+        if labs.is_empty() {
+            continue;
+        }
+        let count = labs.len() as f32;
+        let mean = [0, 1, 2].map(|axis| labs.iter().map(|lab| lab[axis]).sum::<f32>() / count);
+        let spread = [0, 1, 2].map(|axis| {
+            let variance = labs.iter().map(|lab| (lab[axis] - mean[axis]).powi(2)).sum::<f32>() / count;
+            variance.sqrt().max(MIN_SPREAD)
+        });
+        prototypes.push(Prototype {
+            class: label,
+            lab: Lab {
+                l: mean[0],
+                a: mean[1],
+                b: mean[2],
+            },
+            spread,
+        });
+    }
+    if prototypes.len() < 2 {
+        return Err("Need samples of at least two colours to train".to_string());
+    }
+    return Ok(ColourClassifier {
+        prototypes,
+        max_distance: config.max_distance,
+        min_confidence: config.min_confidence,
+    });
+}
+
+/// How often each label was classified as each colour, rows by label.
+pub struct ConfusionMatrix {
+    counts: BTreeMap<(usize, usize), usize>,
+}
+
+// Each sample's fold, by the order of its press among its colour's presses. Counted per
+// colour, as colours are usually recorded in turn and would otherwise line up with folds.
+fn folds(samples: &[Sample]) -> Vec<usize> {
+    let mut presses = BTreeMap::<(usize, usize), usize>::new();
+    for sample in samples {
+        let next = presses.keys().filter(|(label, _)| *label == column(sample.label)).count();
+        presses.entry((column(sample.label), sample.press)).or_insert(next);
+    }
+    return samples.iter().map(|sample| presses[&(column(sample.label), sample.press)] % FOLDS).collect();
+}
+
+// Rows and columns of the matrix: the labels, then unknown as a prediction
+fn column(class: ColourClass) -> usize {
+    return LABELS.iter().position(|label| *label == class).unwrap_or(LABELS.len());
+}
+
+impl ConfusionMatrix {
+    /// Cross-validates over `FOLDS` folds, each holding out every `FOLDS`th press of each colour.
+    pub fn cross_validate(samples: &[Sample], config: &ColourConfig) -> Self {
+        let folds = folds(samples);
+        let mut counts = BTreeMap::new();
+        for fold in 0..FOLDS {
+            let (tested, training): (Vec<_>, Vec<_>) = samples.iter().zip(&folds).partition(|(_, f)| **f == fold);
+            let training: Vec<&Sample> = training.into_iter().map(|(sample, _)| sample).collect();
+            let classifier = train(&training, config).ok();
+            for (sample, _) in tested {
+                let predicted = match &classifier {
+                    Some(classifier) => classifier.classify(&sample.reading, &sample.surface).class,
+                    None => ColourClass::Unknown,
+                };
+                *counts.entry((column(sample.label), column(predicted))).or_default() += 1;
+            }
+        }
+        return Self { counts };
+    }
+
+    fn count(&self, actual: ColourClass, predicted: ColourClass) -> usize {
+        return self.counts.get(&(column(actual), column(predicted))).copied().unwrap_or(0);
+    }
+}
+
+impl Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let predictions: Vec<ColourClass> = LABELS.into_iter().chain([ColourClass::Unknown]).collect();
+        let mut out = format!("{:<8}", "");
+        for predicted in &predictions {
+            let _ = write!(out, "{:>8}", predicted.to_string());
+        }
+        let _ = writeln!(out, "{:>8}", "recall");
+
+        let (mut correct, mut total) = (0, 0);
+        for actual in LABELS {
+            let row: Vec<usize> = predictions.iter().map(|predicted| self.count(actual, *predicted)).collect();
+            let row_total: usize = row.iter().sum();
+            if row_total == 0 {
+                continue;
+            }
+            let _ = write!(out, "{:<8}", actual.to_string());
+            for count in &row {
+                let _ = write!(out, "{:>8}", count);
+            }
+            let right = self.count(actual, actual);
+            let _ = writeln!(out, "{:>7.1}%", 100. * right as f32 / row_total as f32);
+            correct += right;
+            total += row_total;
+        }
+        let _ = writeln!(out, "Accuracy {:.1}% over {} samples", 100. * correct as f32 / total.max(1) as f32, total);
+
+        // What the course depends on
+        let greens: usize = predictions.iter().map(|predicted| self.count(ColourClass::Green, *predicted)).sum();
+        let _ = write!(
+            out,
+            "Green: {} of {} missed; black {} and white {} taken for green",
+            greens - self.count(ColourClass::Green, ColourClass::Green),
+            greens,
+            self.count(ColourClass::Black, ColourClass::Green),
+            self.count(ColourClass::White, ColourClass::Green),
+        );
+        return f.write_str(&out);
+    }
+}
+
+/// `icarus train`: cross-validates the samples, prints the confusion matrix and saves a
+/// classifier trained on all of them to `out`.
+pub fn train_from_file(samples_path: &Path, out: &Path, config: &ColourConfig) -> Result<String, String> {
+    let samples = read_samples(samples_path)?;
+    let classifier = train(&samples.iter().collect::<Vec<_>>(), config)?;
+
+    let mut report = format!("Trained on {} samples from {}\n", samples.len(), samples_path.display());
+    for prototype in &classifier.prototypes {
+        let presses = samples.iter().filter(|s| s.label == prototype.class).map(|s| s.press).collect::<std::collections::BTreeSet<_>>();
+        let _ = writeln!(
+            report,
+            "  {:<7} L {:>6.1} a {:>6.1} b {:>6.1}  spread {:.1}/{:.1}/{:.1}  from {} presses",
+            prototype.class.to_string(),
+            prototype.lab.l,
+            prototype.lab.a,
+            prototype.lab.b,
+            prototype.spread[0],
+            prototype.spread[1],
+            prototype.spread[2],
+            presses.len()
+        );
+        if presses.len() < FOLDS {
+            let _ = writeln!(report, "  (fewer than {} presses of {}, so some folds test it untrained)", FOLDS, prototype.class);
+        }
+    }
+    let _ = writeln!(report, "\nConfusion matrix, {}-fold by press (rows labelled, columns classified):", FOLDS);
+    let _ = writeln!(report, "{}", ConfusionMatrix::cross_validate(&samples, config));
+
+    let count = samples.len() as f32;
+    let mean = |channel: fn(&RGB) -> i32| (samples.iter().map(|s| channel(&s.surface) as f32).sum::<f32>() / count) as i32;
+    let trained = TrainedClassifier {
+        surface: RGB::from((mean(|rgb| rgb.r), mean(|rgb| rgb.g), mean(|rgb| rgb.b))),
+        samples: samples.len(),
+        classifier,
+    };
+    let text = serde_json::to_string_pretty(&trained).map_err(|e| e.to_string())?;
+    fs::write(out, text + "\n").map_err(|e| format!("{}: {}", out.display(), e))?;
+    let _ = writeln!(report, "\nSaved to {}; set colour.trained to use it", out.display());
+    return Ok(report);
+}
+
+impl LineFollowRobot {
+    /// Calibrates, then records labelled readings from every line sensor into `path`. Up and
+    /// down pick the colour the robot is sitting on, enter records, back finishes.
+    pub fn collect_samples(&mut self, path: &Path, menu: &mut Menu) -> IcarusResult<()> {
+        self.calibrate()?;
+        let Some(profile) = self.calibration.clone() else {
+            return Ok(());
+        };
+        let surfaces: Vec<RGB> = [profile.left, profile.right].into_iter().chain(profile.extra).collect();
+        // Carry on numbering presses from an earlier session so folds stay spread out
+        let mut press = read_samples(path).map(|samples| samples.iter().map(|s| s.press + 1).max().unwrap_or(0)).unwrap_or(0);
+        let mut label = 0;
+        let mut recorded = BTreeMap::<String, usize>::new();
+
+        loop {
+            let counts: Vec<String> = recorded.iter().map(|(label, count)| format!("{} {}", label, count)).collect();
+            let shown = menu.message(
+                "COLLECT",
+                &[&format!("On: {}", LABELS[label]), "Up/down: colour", "Enter: record", "Back: finish", &counts.join(" ")],
+            );
+            if let Err(e) = shown {
+                Icarus::warn(format!("Could not draw on the screen: {}", e));
+            }
+
+            let pressed = match &mut self.buttons {
+                Some(buttons) => buttons.next_press(),
+                None => Ok(None),
+            };
+            match pressed {
+                Ok(Some(Button::Up)) => label = (label + LABELS.len() - 1) % LABELS.len(),
+                Ok(Some(Button::Down)) => label = (label + 1) % LABELS.len(),
+                Ok(Some(Button::Enter)) => {
+                    let mut samples = Vec::new();
+                    for _ in 0..SAMPLES_PER_PRESS {
+                        sleep(Duration::from_millis(10));
+                        for (sensor, (reading, surface)) in self.read_line_sensors()?.into_iter().zip(&surfaces).enumerate() {
+                            samples.push(Sample {
+                                label: LABELS[label],
+                                press,
+                                sensor,
+                                reading,
+                                surface: surface.clone(),
+                            });
+                        }
+                    }
+                    if let Err(e) = append_samples(path, &samples) {
+                        Icarus::error(format!("Could not save samples to {}: {}", path.display(), e));
+                        return Ok(());
+                    }
+                    press += 1;
+                    *recorded.entry(LABELS[label].to_string()).or_default() += samples.len();
+                    Icarus::info(format!("Recorded {} {} samples", samples.len(), LABELS[label]));
+                }
+                Ok(Some(Button::Back)) | Ok(None) => break,
+                Ok(Some(Button::Left | Button::Right)) => {}
+                Err(e) => {
+                    Icarus::error(format!("Button input failed: {}", e));
+                    break;
+                }
+            }
+        }
+        Icarus::info(format!("Samples saved to {}; run `icarus train` on them", path.display()));
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SURFACE: (i32, i32, i32) = (180, 190, 170);
+
+    // Colours recorded in turn, `presses` presses each, with a little jitter within a press
+    fn recorded(colours: &[(ColourClass, (i32, i32, i32))], presses: usize) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        for round in 0..presses {
+            for (i, (label, (r, g, b))) in colours.iter().enumerate() {
+                let press = round * colours.len() + i;
+                for reading in 0..SAMPLES_PER_PRESS as i32 {
+                    let jitter = reading % 5 - 2;
+                    samples.push(Sample {
+                        label: *label,
+                        press,
+                        sensor: reading as usize % 2,
+                        reading: RGB::from((r + jitter, g - jitter, b + jitter)),
+                        surface: RGB::from(SURFACE),
+                    });
+                }
+            }
+        }
+        return samples;
+    }
+
+    fn course_colours() -> Vec<(ColourClass, (i32, i32, i32))> {
+        return vec![
+            (ColourClass::White, SURFACE),
+            (ColourClass::Black, (25, 25, 25)),
+            (ColourClass::Green, (30, 70, 30)),
+            (ColourClass::Red, (110, 30, 22)),
+            (ColourClass::Silver, (200, 212, 190)),
+        ];
+    }
+
+    // A scratch path that is removed again when dropped
+    struct Scratch(std::path::PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            return Self(std::env::temp_dir().join(format!("icarus-{}-{}", std::process::id(), name)));
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn every_colour_is_spread_over_every_fold() {
+        // Five colours in turn put every press of a colour in the same fold if folded by press number
+        let samples = recorded(&course_colours(), FOLDS);
+        let folds = folds(&samples);
+        for label in LABELS {
+            let mut seen: Vec<usize> = samples.iter().zip(&folds).filter(|(s, _)| s.label == label).map(|(_, f)| *f).collect();
+            seen.dedup();
+            assert_eq!(seen, (0..FOLDS).collect::<Vec<_>>(), "{}", label);
+        }
+    }
+
+    #[test]
+    fn readings_of_one_press_share_a_fold() {
+        let samples = recorded(&course_colours(), 2 * FOLDS);
+        let folds = folds(&samples);
+        for (sample, fold) in samples.iter().zip(&folds) {
+            let first = samples.iter().position(|s| s.press == sample.press).unwrap();
+            assert_eq!(*fold, folds[first]);
+        }
+    }
+
+    #[test]
+    fn cross_validation_separates_distinct_colours() {
+        let samples = recorded(&course_colours(), FOLDS);
+        let matrix = ConfusionMatrix::cross_validate(&samples, &ColourConfig::default());
+        for label in LABELS {
+            assert_eq!(matrix.count(label, label), FOLDS * SAMPLES_PER_PRESS, "{}\n{}", label, matrix);
+        }
+    }
+
+    #[test]
+    fn trained_model_round_trips_through_files() {
+        let samples = recorded(&course_colours(), FOLDS);
+        let (csv, json) = (Scratch::new("samples.csv"), Scratch::new("trained.json"));
+        append_samples(&csv.0, &samples).unwrap();
+        assert_eq!(read_samples(&csv.0).unwrap().len(), samples.len());
+
+        train_from_file(&csv.0, &json.0, &ColourConfig::default()).unwrap();
+        let trained = TrainedClassifier::load(&json.0).unwrap();
+        assert_eq!(trained.samples, samples.len());
+        assert_eq!((trained.surface.r, trained.surface.g, trained.surface.b), SURFACE);
+
+        let direct = train(&samples.iter().collect::<Vec<_>>(), &ColourConfig::default()).unwrap();
+        for (label, reading) in course_colours() {
+            let reading = RGB::from(reading);
+            let loaded = trained.classifier.classify(&reading, &RGB::from(SURFACE));
+            assert_eq!(loaded.class, label);
+            assert_eq!(loaded.class, direct.classify(&reading, &RGB::from(SURFACE)).class);
+        }
+    }
+
+    #[test]
+    fn one_colour_is_not_enough() {
+        let samples = recorded(&course_colours()[..1], 2);
+        assert!(train(&samples.iter().collect::<Vec<_>>(), &ColourConfig::default()).is_err());
+    }
+}